@@ -0,0 +1,206 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rsoderh_chess::{Board, PieceKind, Position, Slot};
+
+use puhl_gui::protocol::{classify_move, MoveType};
+use crate::state::ChessState;
+
+// Xorshift random numbers for the computer's choices, seeded so that a game
+// can be played again with `--seed`
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift is stuck at zero, so nudge that seed to a nonzero state
+        Self { state: if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed } }
+    }
+
+    // Seed from the clock, returning the seed too so it can be reported
+    pub fn from_entropy() -> (Self, u64) {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+        (Self::new(seed), seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+// Material value of a piece. The king can't be captured, so it is worth nothing.
+fn piece_value(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Pawn => 1,
+        PieceKind::Knight | PieceKind::Bishop => 3,
+        PieceKind::Rook => 5,
+        PieceKind::Queen => 9,
+        PieceKind::King => 0,
+    }
+}
+
+// Material gained by playing `mv`: the captured piece, plus the queen a pawn
+// promotes to
+fn material_gain(board: &Board, mv: (Position, Position), promotes: bool) -> i32 {
+    let captured = match board.at_position(mv.1) {
+        Slot::Occupied(piece) => piece_value(piece.kind),
+        Slot::Empty if classify_move(board, mv) == MoveType::EnPassant => piece_value(PieceKind::Pawn),
+        Slot::Empty => 0,
+    };
+    let promotion = if promotes { piece_value(PieceKind::Queen) - piece_value(PieceKind::Pawn) } else { 0 };
+    captured + promotion
+}
+
+// One-ply greedy choice among `moves`: the one winning the most material, with
+// ties broken by `seed` so the opponent doesn't play the same game every time
+fn best_move(board: &Board, moves: &[(Position, Position, bool)], seed: u64) -> Option<(Position, Position, bool)> {
+    let best = moves.iter().map(|&(src, dest, promotes)| material_gain(board, (src, dest), promotes)).max()?;
+    let ties: Vec<_> = moves
+        .iter()
+        .copied()
+        .filter(|&(src, dest, promotes)| material_gain(board, (src, dest), promotes) == best)
+        .collect();
+    Some(ties[(seed % ties.len() as u64) as usize])
+}
+
+// Pieces a pawn may promote to in random play
+const PROMOTIONS: [PieceKind; 4] = [PieceKind::Queen, PieceKind::Rook, PieceKind::Bishop, PieceKind::Knight];
+
+// Any of `moves`, chosen by `seed`, with a promotion piece chosen by it too
+fn random_pick(moves: &[(Position, Position, bool)], seed: u64) -> Option<(Position, Position, Option<PieceKind>)> {
+    if moves.is_empty() {
+        return None;
+    }
+    let count = moves.len() as u64;
+    let (src, dest, promotes) = moves[(seed % count) as usize];
+    let kind = PROMOTIONS[(seed / count % PROMOTIONS.len() as u64) as usize];
+    Some((src, dest, promotes.then_some(kind)))
+}
+
+// Every legal move for the side to move, and whether it promotes
+fn legal_moves(state: &ChessState) -> Vec<(Position, Position, bool)> {
+    let mut moves = Vec::new();
+    for column in 0..8 {
+        for row in 0..8 {
+            let src = Position::new(column, row).unwrap();
+            if !state.movable(src) {
+                continue;
+            }
+            for dest in state.game.valid_moves(src).into_iter().flatten() {
+                moves.push((src, dest, state.promotes(src, dest).is_some()));
+            }
+        }
+    }
+    moves
+}
+
+// Move for the side to move, or None if it has no legal moves. Pawns always
+// promote to a queen.
+pub fn choose_move(state: &ChessState, seed: u64) -> Option<((Position, Position), Option<PieceKind>)> {
+    let (source, dest, promotes) = best_move(state.board(), &legal_moves(state), seed)?;
+    Some(((source, dest), promotes.then_some(PieceKind::Queen)))
+}
+
+// Any legal move for the side to move, for `--demo`, or None if it has none.
+// Pawns promote to any piece.
+pub fn random_move(state: &ChessState, seed: u64) -> Option<((Position, Position), Option<PieceKind>)> {
+    let (source, dest, prom) = random_pick(&legal_moves(state), seed)?;
+    Some(((source, dest), prom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsoderh_chess::{Color, Piece};
+
+    fn pos(s: &str) -> Position {
+        Position::parse(s).unwrap()
+    }
+
+    fn board_with(pieces: &[(&str, Color, PieceKind)]) -> Board {
+        let mut board = Board::new_empty();
+        for &(square, color, kind) in pieces {
+            *board.at_position_mut(pos(square)) = Slot::Occupied(Piece { color, kind });
+        }
+        board
+    }
+
+    #[test]
+    fn takes_the_most_valuable_piece() {
+        let board = board_with(&[
+            ("d4", Color::White, PieceKind::Queen),
+            ("d7", Color::Black, PieceKind::Rook),
+            ("g7", Color::Black, PieceKind::Knight),
+        ]);
+        let moves = [
+            (pos("d4"), pos("d5"), false),
+            (pos("d4"), pos("g7"), false),
+            (pos("d4"), pos("d7"), false),
+        ];
+        for seed in 0..3 {
+            assert_eq!(best_move(&board, &moves, seed), Some((pos("d4"), pos("d7"), false)));
+        }
+    }
+
+    #[test]
+    fn promotion_counts_as_material() {
+        let board = board_with(&[
+            ("a7", Color::White, PieceKind::Pawn),
+            ("c3", Color::White, PieceKind::Bishop),
+            ("e5", Color::Black, PieceKind::Rook),
+        ]);
+        let moves = [(pos("c3"), pos("e5"), false), (pos("a7"), pos("a8"), true)];
+        assert_eq!(best_move(&board, &moves, 0), Some((pos("a7"), pos("a8"), true)));
+    }
+
+    #[test]
+    fn en_passant_wins_a_pawn() {
+        let board = board_with(&[("e5", Color::White, PieceKind::Pawn), ("d5", Color::Black, PieceKind::Pawn)]);
+        assert_eq!(material_gain(&board, (pos("e5"), pos("d6")), false), 1);
+        assert_eq!(material_gain(&board, (pos("e5"), pos("e6")), false), 0);
+    }
+
+    #[test]
+    fn seed_picks_among_equal_moves() {
+        let board = Board::new_empty();
+        let moves = [(pos("e2"), pos("e3"), false), (pos("e2"), pos("e4"), false)];
+        assert_eq!(best_move(&board, &moves, 0), Some(moves[0]));
+        assert_eq!(best_move(&board, &moves, 1), Some(moves[1]));
+        assert_eq!(best_move(&board, &[], 0), None);
+    }
+
+    #[test]
+    fn same_seed_plays_the_same_moves() {
+        let board = Board::new_empty();
+        let moves = [
+            (pos("b1"), pos("a3"), false),
+            (pos("b1"), pos("c3"), false),
+            (pos("g1"), pos("f3"), false),
+            (pos("g1"), pos("h3"), false),
+        ];
+        let picks = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..20).map(|_| best_move(&board, &moves, rng.next_u64())).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(42), picks(42));
+        assert_ne!(picks(42), picks(43));
+        assert_ne!(Rng::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn random_pick_reaches_every_move_and_promotion() {
+        let moves = [(pos("a7"), pos("a8"), true), (pos("c2"), pos("c3"), false), (pos("c2"), pos("c4"), false)];
+        let picks: Vec<_> = (0..12).filter_map(|seed| random_pick(&moves, seed)).collect();
+        for &(src, dest, _) in &moves {
+            assert!(picks.iter().any(|&(s, d, _)| (s, d) == (src, dest)));
+        }
+        for kind in PROMOTIONS {
+            assert!(picks.contains(&(pos("a7"), pos("a8"), Some(kind))));
+        }
+        assert!(picks.iter().all(|&(src, _, kind)| kind.is_none() == (src == pos("c2"))));
+        assert_eq!(random_pick(&[], 7), None);
+    }
+}
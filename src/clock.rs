@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use rsoderh_chess::Color;
+
+// Time each side starts with, and the time added after each of its moves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+// Chess clocks for both sides. Only the side to move runs down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clock {
+    control: TimeControl,
+    white: Duration,
+    black: Duration,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self { control, white: control.base, black: control.base }
+    }
+
+    // Both sides back to the starting time
+    pub fn reset(&mut self) {
+        *self = Self::new(self.control);
+    }
+
+    pub fn remaining(&self, color: Color) -> Duration {
+        match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        }
+    }
+
+    // Run `color`'s clock down by `dt`. Returns true if it has run out.
+    pub fn tick(&mut self, color: Color, dt: Duration) -> bool {
+        let remaining = match color {
+            Color::White => &mut self.white,
+            Color::Black => &mut self.black,
+        };
+        *remaining = remaining.saturating_sub(dt);
+        remaining.is_zero()
+    }
+
+    // Give `color` its increment for the move it just made
+    pub fn add_increment(&mut self, color: Color) {
+        match color {
+            Color::White => self.white += self.control.increment,
+            Color::Black => self.black += self.control.increment,
+        }
+    }
+}
+
+// Time each side has spent on its moves, shown when there are no clocks.
+// Purely informational: it counts up and never ends the game.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimeSpent {
+    white: Duration,
+    black: Duration,
+}
+
+impl TimeSpent {
+    pub fn spent(&self, color: Color) -> Duration {
+        match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        }
+    }
+
+    // Add `dt` to `color`'s time
+    pub fn tick(&mut self, color: Color, dt: Duration) {
+        match color {
+            Color::White => self.white += dt,
+            Color::Black => self.black += dt,
+        }
+    }
+}
+
+// Time spent as `mm:ss`, going on to `mmm:ss` past 100 minutes
+pub fn format_spent(spent: Duration) -> String {
+    let seconds = spent.as_secs();
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Remaining time as `m:ss`, or `0:ss.t` in the last ten seconds
+pub fn format_clock(remaining: Duration) -> String {
+    let seconds = remaining.as_secs();
+    if seconds < 10 {
+        format!("0:{:02}.{}", seconds, remaining.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(minutes: u64) -> TimeControl {
+        TimeControl { base: Duration::from_secs(60 * minutes), increment: Duration::ZERO }
+    }
+
+    #[test]
+    fn only_the_ticked_side_runs_down() {
+        let mut clock = Clock::new(minutes(1));
+        assert!(!clock.tick(Color::White, Duration::from_secs(15)));
+        assert_eq!(clock.remaining(Color::White), Duration::from_secs(45));
+        assert_eq!(clock.remaining(Color::Black), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn flag_falls_at_zero() {
+        let mut clock = Clock::new(TimeControl { base: Duration::from_secs(1), increment: Duration::ZERO });
+        assert!(clock.tick(Color::Black, Duration::from_millis(1500)), "overshooting stops at zero");
+        assert_eq!(clock.remaining(Color::Black), Duration::ZERO);
+
+        clock.reset();
+        assert_eq!(clock.remaining(Color::Black), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn increment_goes_to_the_mover() {
+        let mut clock = Clock::new(TimeControl { base: Duration::from_secs(180), increment: Duration::from_secs(2) });
+        clock.tick(Color::White, Duration::from_secs(10));
+        clock.add_increment(Color::White);
+        assert_eq!(clock.remaining(Color::White), Duration::from_secs(172));
+        assert_eq!(clock.remaining(Color::Black), Duration::from_secs(180));
+
+        clock.reset();
+        assert_eq!(clock.remaining(Color::White), Duration::from_secs(180));
+    }
+
+    #[test]
+    fn clock_format() {
+        assert_eq!(format_clock(Duration::from_secs(300)), "5:00");
+        assert_eq!(format_clock(Duration::from_secs(71)), "1:11");
+        assert_eq!(format_clock(Duration::from_millis(9_450)), "0:09.4");
+    }
+
+    #[test]
+    fn time_spent_counts_up_per_side() {
+        let mut spent = TimeSpent::default();
+        spent.tick(Color::White, Duration::from_secs(5));
+        spent.tick(Color::Black, Duration::from_millis(1500));
+        spent.tick(Color::White, Duration::from_secs(70));
+        assert_eq!(spent.spent(Color::White), Duration::from_secs(75));
+        assert_eq!(spent.spent(Color::Black), Duration::from_millis(1500));
+
+        assert_eq!(format_spent(spent.spent(Color::White)), "01:15");
+        assert_eq!(format_spent(spent.spent(Color::Black)), "00:01");
+        assert_eq!(format_spent(Duration::from_secs(6001)), "100:01");
+    }
+}
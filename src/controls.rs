@@ -0,0 +1,135 @@
+use ggez::input::keyboard::KeyCode;
+use rsoderh_chess::PieceKind;
+
+// What a key does. `KEY_BINDINGS` maps keys to these for `key_down_event` and
+// describes them for the help overlay, so the two can't drift apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Takeback,
+    Save,
+    Load,
+    OfferDraw,
+    CopyFen,
+    HistoryBack,
+    HistoryForward,
+    // Keyboard cursor: Left and Right move it too while it is shown
+    CursorUp,
+    CursorDown,
+    Activate,
+    HideCursor,
+    Promote(PieceKind),
+    ToggleHighlights,
+    TogglePins,
+    ResetZoom,
+    ToggleHelp,
+    // Only with `--debug`
+    DumpPosition,
+}
+
+// Keys that do related things, listed as one line of the help
+pub struct Binding {
+    pub keys: &'static [(KeyCode, Action)],
+    pub label: &'static str,
+    pub help: &'static str,
+}
+
+pub const KEY_BINDINGS: &[Binding] = &[
+    Binding {
+        keys: &[(KeyCode::F1, Action::ToggleHelp), (KeyCode::Slash, Action::ToggleHelp)],
+        label: "F1 / ?",
+        help: "show or hide this help",
+    },
+    Binding {
+        keys: &[(KeyCode::KeyT, Action::Takeback)],
+        label: "T",
+        help: "take back the last move (asks the opponent online)",
+    },
+    Binding {
+        keys: &[(KeyCode::KeyD, Action::OfferDraw)],
+        label: "D",
+        help: "offer a draw, or accept the opponent's offer",
+    },
+    Binding { keys: &[(KeyCode::KeyS, Action::Save)], label: "S", help: "save the game" },
+    Binding { keys: &[(KeyCode::KeyL, Action::Load)], label: "L", help: "load the saved game" },
+    Binding { keys: &[(KeyCode::KeyC, Action::CopyFen)], label: "C", help: "copy the position as FEN" },
+    Binding {
+        keys: &[(KeyCode::ArrowLeft, Action::HistoryBack), (KeyCode::ArrowRight, Action::HistoryForward)],
+        label: "Left / Right",
+        help: "step through the moves played so far",
+    },
+    Binding {
+        keys: &[(KeyCode::ArrowUp, Action::CursorUp), (KeyCode::ArrowDown, Action::CursorDown)],
+        label: "Up / Down",
+        help: "show the keyboard cursor and move it; Left / Right move it too while it is shown",
+    },
+    Binding {
+        keys: &[(KeyCode::Space, Action::Activate), (KeyCode::Enter, Action::Activate)],
+        label: "Space / Enter",
+        help: "select the piece under the keyboard cursor, or move the selected piece there",
+    },
+    Binding { keys: &[(KeyCode::Escape, Action::HideCursor)], label: "Esc", help: "hide the keyboard cursor" },
+    Binding {
+        keys: &[
+            (KeyCode::KeyQ, Action::Promote(PieceKind::Queen)),
+            (KeyCode::KeyR, Action::Promote(PieceKind::Rook)),
+            (KeyCode::KeyB, Action::Promote(PieceKind::Bishop)),
+            (KeyCode::KeyN, Action::Promote(PieceKind::Knight)),
+        ],
+        label: "Q / R / B / N",
+        help: "promote to a queen, rook, bishop or knight",
+    },
+    Binding { keys: &[(KeyCode::KeyH, Action::ToggleHighlights)], label: "H", help: "toggle the move highlights" },
+    Binding { keys: &[(KeyCode::KeyP, Action::TogglePins)], label: "P", help: "toggle the pinned-piece outlines" },
+    Binding { keys: &[(KeyCode::Digit0, Action::ResetZoom)], label: "0", help: "fit the board to the window again" },
+    Binding { keys: &[(KeyCode::F12, Action::DumpPosition)], label: "F12", help: "print the position as JSON" },
+];
+
+// Mouse actions, for the help overlay only
+pub const MOUSE_HELP: &[(&str, &str)] = &[
+    ("Left-click", "select a piece, then click a highlighted square to move it"),
+    ("Right-click", "toggle a highlight on a square"),
+    ("Right-drag", "toggle an arrow between two squares"),
+    ("Mouse wheel", "zoom the board in and out"),
+    ("Middle-drag", "move the zoomed board"),
+];
+
+// The action bound to `key`, if any
+pub fn action_for(key: KeyCode) -> Option<Action> {
+    KEY_BINDINGS.iter().flat_map(|binding| binding.keys).find(|(bound, _)| *bound == key).map(|&(_, action)| action)
+}
+
+// Lines of the help overlay, leaving out keys that only work with `--debug`
+// unless `debug` is set
+pub fn help_lines(debug: bool) -> Vec<String> {
+    let mouse = MOUSE_HELP.iter().map(|(label, help)| format!("{label}: {help}"));
+    let keys = KEY_BINDINGS
+        .iter()
+        .filter(|binding| debug || !binding.keys.iter().any(|&(_, action)| action == Action::DumpPosition))
+        .map(|binding| format!("{}: {}", binding.label, binding.help));
+    mouse.chain(keys).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_has_one_action() {
+        let keys: Vec<KeyCode> = KEY_BINDINGS.iter().flat_map(|binding| binding.keys).map(|&(key, _)| key).collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key), "{key:?} is bound twice");
+        }
+        assert_eq!(action_for(KeyCode::KeyN), Some(Action::Promote(PieceKind::Knight)));
+        assert_eq!(action_for(KeyCode::Slash), Some(Action::ToggleHelp));
+        assert_eq!(action_for(KeyCode::Enter), Some(Action::Activate));
+        assert_eq!(action_for(KeyCode::Tab), None);
+    }
+
+    #[test]
+    fn help_lists_every_binding() {
+        let lines = help_lines(true);
+        assert_eq!(lines.len(), MOUSE_HELP.len() + KEY_BINDINGS.len());
+        assert!(lines.contains(&"F12: print the position as JSON".to_string()));
+        assert!(!help_lines(false).iter().any(|line| line.starts_with("F12")));
+    }
+}
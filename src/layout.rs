@@ -0,0 +1,386 @@
+use ggez::graphics::Rect;
+use rsoderh_chess::{Color, Position, PositionIndex};
+
+use crate::state::promotion_rank;
+
+pub const FILES: usize = 8;
+pub const RANKS: usize = 8;
+pub const STATUS_BAR_HEIGHT: f32 = 32.0;
+// Bounds of the mouse wheel zoom, relative to the board just fitting the window
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 4.0;
+
+// Where the board sits in the window. The status bar runs along the bottom, and
+// the board is kept square and centered in the space above it, letterboxing
+// whichever dimension is longer. Zooming and panning then scale and move the
+// board from there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub width: f32,
+    pub height: f32,
+    pub origin: [f32; 2],
+    pub square_size: f32,
+    // Black at the bottom, with the files running h to a
+    pub flipped: bool,
+    // Board size relative to fitting the window
+    pub zoom: f32,
+}
+
+// Whether to draw Black at the bottom. Playing Black over the network turns
+// the board, and so does `--flip`; either one is enough, so the two never
+// cancel out.
+pub fn black_at_bottom(flip: bool, network_color: Option<Color>) -> bool {
+    flip || network_color == Some(Color::Black)
+}
+
+impl Layout {
+    pub fn new(width: f32, height: f32) -> Self {
+        let board_height = (height - STATUS_BAR_HEIGHT).max(0.0);
+        let board_size = width.min(board_height);
+        Self {
+            width,
+            height,
+            origin: [(width - board_size) / 2.0, (board_height - board_size) / 2.0],
+            square_size: board_size / FILES as f32,
+            flipped: false,
+            zoom: 1.0,
+        }
+    }
+
+    pub fn with_flipped(self, flipped: bool) -> Self {
+        Self { flipped, ..self }
+    }
+
+    // Scale the board by `factor`, within the zoom bounds, keeping the point
+    // under `anchor` (e.g. the mouse) where it is
+    pub fn zoom_at(self, factor: f32, anchor: [f32; 2]) -> Self {
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let scale = zoom / self.zoom;
+        let origin = [
+            anchor[0] - (anchor[0] - self.origin[0]) * scale,
+            anchor[1] - (anchor[1] - self.origin[1]) * scale,
+        ];
+        Self { origin, square_size: self.square_size * scale, zoom, ..self }
+    }
+
+    // Move the board by `delta` pixels, e.g. to see another part of it zoomed in
+    pub fn panned(self, delta: [f32; 2]) -> Self {
+        Self { origin: [self.origin[0] + delta[0], self.origin[1] + delta[1]], ..self }
+    }
+
+    // The board fitting the window again, as when it opened
+    pub fn reset_zoom(self) -> Self {
+        Self::new(self.width, self.height).with_flipped(self.flipped)
+    }
+
+    // The same zoom in a resized window, centered again
+    pub fn resized(self, width: f32, height: f32) -> Self {
+        let fitted = Self::new(width, height).with_flipped(self.flipped);
+        fitted.zoom_at(self.zoom, fitted.board_center())
+    }
+
+    // Screen column and row of a square, counted from the top-left corner
+    fn screen_cell(&self, position: Position) -> (u8, u8) {
+        if self.flipped {
+            (7 - position.column(), position.row())
+        } else {
+            (position.column(), 7 - position.row())
+        }
+    }
+
+    // The whole window, for dimming overlays
+    pub fn window_rect(&self) -> Rect {
+        Rect::new(0.0, 0.0, self.width, self.height)
+    }
+
+    pub fn status_bar_rect(&self) -> Rect {
+        Rect::new(0.0, self.height - STATUS_BAR_HEIGHT, self.width, STATUS_BAR_HEIGHT)
+    }
+
+    pub fn board_rect(&self) -> Rect {
+        let size = self.square_size * FILES as f32;
+        Rect::new(self.origin[0], self.origin[1], size, size)
+    }
+
+    pub fn board_center(&self) -> [f32; 2] {
+        let board = self.board_rect();
+        [board.x + board.w / 2.0, board.y + board.h / 2.0]
+    }
+
+    // Top-left screen coordinate of a square
+    pub fn square_origin(&self, position: Position) -> [f32; 2] {
+        let (column, row) = self.screen_cell(position);
+        [
+            self.origin[0] + column as f32 * self.square_size,
+            self.origin[1] + row as f32 * self.square_size,
+        ]
+    }
+
+    pub fn square_rect(&self, position: Position) -> Rect {
+        let [x, y] = self.square_origin(position);
+        Rect::new(x, y, self.square_size, self.square_size)
+    }
+
+    // Screen coordinate of the center of a square
+    pub fn square_center(&self, position: Position) -> [f32; 2] {
+        let [x, y] = self.square_origin(position);
+        [x + self.square_size / 2.0, y + self.square_size / 2.0]
+    }
+
+    // The square `dx` columns right and `dy` rows down the screen from
+    // `position`, if that is still on the board
+    pub fn step(&self, position: Position, dx: i8, dy: i8) -> Option<Position> {
+        let (files, ranks) = if self.flipped { (-dx, dy) } else { (dx, -dy) };
+        let file = position.column().checked_add_signed(files)?;
+        let rank = position.row().checked_add_signed(ranks)?;
+        Position::new(file, rank)
+    }
+
+    // Square under a screen coordinate, if it lies on the board. A zoomed-in
+    // board reaching under the status bar can't be clicked there.
+    pub fn position_at(&self, x: f32, y: f32) -> Option<Position> {
+        if y >= self.height - STATUS_BAR_HEIGHT {
+            return None;
+        }
+        let x = x - self.origin[0];
+        let y = y - self.origin[1];
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let column = (x / self.square_size).floor() as u8;
+        let row = (y / self.square_size).floor() as u8;
+        if column >= FILES as u8 || row >= RANKS as u8 {
+            return None;
+        }
+        if self.flipped {
+            Position::new(7 - column, row)
+        } else {
+            Position::new(column, 7 - row)
+        }
+    }
+
+    // Tiles of the promotion choices, in a strip running from the promotion square
+    // into the board: top-down when it is at the top, bottom-up when it is at the
+    // bottom. The strip is shifted back on screen if it would stick out.
+    pub fn promotion_tiles(&self, column: PositionIndex, color: Color) -> [Rect; 4] {
+        let size = self.square_size;
+        let [x, y] = self.square_origin(Position::new(column.get(), promotion_rank(color)).unwrap());
+        let downwards = y < self.board_center()[1];
+        let top = if downwards { y } else { y - 3.0 * size };
+        let top = top.min(self.height - STATUS_BAR_HEIGHT - 4.0 * size).max(0.0);
+
+        std::array::from_fn(|i| {
+            let i = if downwards { i } else { 3 - i };
+            Rect::new(x, top + i as f32 * size, size, size)
+        })
+    }
+
+    // "Save PGN" button under the game over banner
+    pub fn save_pgn_button(&self) -> Rect {
+        let size = self.square_size;
+        let [center_x, center_y] = self.board_center();
+        Rect::new(center_x - 1.5 * size, center_y + 1.75 * size, 3.0 * size, 0.75 * size)
+    }
+
+    // "Resign" and "Offer Draw" buttons at the right end of the status bar
+    pub fn toolbar_buttons(&self) -> (Rect, Rect) {
+        let bar = self.status_bar_rect();
+        let margin = 4.0;
+        let width = 3.5 * STATUS_BAR_HEIGHT;
+        let height = bar.h - 2.0 * margin;
+        let draw = Rect::new(bar.x + bar.w - margin - width, bar.y + margin, width, height);
+        let resign = Rect::new(draw.x - margin - width, bar.y + margin, width, height);
+        (resign, draw)
+    }
+
+    // "Yes" and "No" buttons of the play-again overlay, below the winner banner
+    pub fn confirm_reset_buttons(&self) -> (Rect, Rect) {
+        let size = self.square_size;
+        let [center_x, center_y] = self.board_center();
+        let y = center_y + 1.75 * size;
+        let yes = Rect::new(center_x - 2.25 * size, y, 2.0 * size, size);
+        let no = Rect::new(center_x + 0.25 * size, y, 2.0 * size, size);
+        (yes, no)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn board_is_letterboxed_in_wide_window() {
+        let layout = Layout::new(1200.0, 800.0 + STATUS_BAR_HEIGHT);
+        assert_eq!(layout.square_size, 100.0);
+        assert_eq!(layout.origin, [200.0, 0.0]);
+
+        assert_eq!(layout.position_at(150.0, 400.0), None, "left letterbox");
+        assert_eq!(layout.position_at(250.0, 750.0), Position::new(0, 0));
+        assert_eq!(layout.position_at(1050.0, 50.0), None, "right letterbox");
+    }
+
+    #[test]
+    fn status_bar_is_below_the_board() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        assert_eq!(layout.board_rect(), Rect::new(0.0, 0.0, 800.0, 800.0));
+        assert_eq!(layout.status_bar_rect(), Rect::new(0.0, 800.0, 800.0, STATUS_BAR_HEIGHT));
+    }
+
+    #[test]
+    fn square_origin_and_position_at_agree() {
+        let layout = Layout::new(640.0, 900.0);
+        for col in 0..8 {
+            for row in 0..8 {
+                let position = Position::new(col, row).unwrap();
+                let [x, y] = layout.square_center(position);
+                assert_eq!(layout.position_at(x, y), Some(position));
+            }
+        }
+    }
+
+    #[test]
+    fn clicks_at_the_window_edge() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        assert_eq!(layout.position_at(0.0, 0.0), Position::parse("a8"));
+        assert_eq!(layout.position_at(799.9, 799.9), Position::parse("h1"));
+
+        assert_eq!(layout.position_at(800.0, 400.0), None);
+        assert_eq!(layout.position_at(400.0, 800.0), None, "status bar");
+        assert_eq!(layout.position_at(800.5, 800.5), None);
+        assert_eq!(layout.position_at(-0.5, 400.0), None);
+        assert_eq!(layout.position_at(400.0, -0.5), None);
+    }
+
+    #[test]
+    fn flipped_board_puts_black_at_the_bottom() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT).with_flipped(true);
+        assert_eq!(layout.position_at(50.0, 50.0), Position::parse("h1"));
+        assert_eq!(layout.position_at(750.0, 750.0), Position::parse("a8"));
+        assert_eq!(layout.square_origin(Position::parse("a8").unwrap()), [700.0, 700.0]);
+
+        for col in 0..8 {
+            for row in 0..8 {
+                let position = Position::new(col, row).unwrap();
+                let [x, y] = layout.square_center(position);
+                assert_eq!(layout.position_at(x, y), Some(position));
+            }
+        }
+    }
+
+    #[test]
+    fn zoom_keeps_the_anchor_in_place() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        // Zooming in on the middle of e4
+        let anchor = layout.square_center(Position::parse("e4").unwrap());
+        let zoomed = layout.zoom_at(2.0, anchor);
+        assert_eq!(zoomed.square_size, 200.0);
+        assert_eq!(zoomed.square_center(Position::parse("e4").unwrap()), anchor);
+        assert_eq!(zoomed.position_at(anchor[0], anchor[1]), Position::parse("e4"));
+        assert_eq!(zoomed.position_at(anchor[0] + 150.0, anchor[1]), Position::parse("f4"));
+        assert_eq!(zoomed.position_at(0.0, 0.0), Position::parse("c6"), "a8 is off screen now");
+
+        // Panning moves the squares under the mouse
+        let panned = zoomed.panned([-200.0, 0.0]);
+        assert_eq!(panned.position_at(anchor[0], anchor[1]), Position::parse("f4"));
+        assert_eq!(panned.reset_zoom(), layout);
+    }
+
+    #[test]
+    fn zoom_is_clamped() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        assert_eq!(layout.zoom_at(100.0, [0.0, 0.0]).zoom, MAX_ZOOM);
+        let out = layout.zoom_at(0.01, [400.0, 400.0]);
+        assert_eq!(out.zoom, MIN_ZOOM);
+        assert_eq!(out.board_rect(), Rect::new(200.0, 200.0, 400.0, 400.0), "margin around a small board");
+        assert_eq!(out.position_at(100.0, 100.0), None);
+    }
+
+    #[test]
+    fn zoomed_board_under_the_status_bar_is_not_clickable() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT).zoom_at(2.0, [400.0, 400.0]);
+        assert_eq!(layout.position_at(400.0, 790.0), Position::parse("e3"));
+        assert_eq!(layout.position_at(400.0, 810.0), None);
+    }
+
+    #[test]
+    fn resizing_keeps_the_zoom() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT).zoom_at(1.5, [0.0, 0.0]).with_flipped(true);
+        let resized = layout.resized(400.0, 400.0 + STATUS_BAR_HEIGHT);
+        assert_eq!(resized.zoom, 1.5);
+        assert_eq!(resized.square_size, 75.0);
+        assert!(resized.flipped);
+        assert_eq!(resized.board_center(), [200.0, 200.0]);
+    }
+
+    #[test]
+    fn save_pgn_button_is_centered_below_the_banner() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        let button = layout.save_pgn_button();
+        assert_eq!(button.x + button.w / 2.0, 400.0);
+        assert!(button.y > 400.0 && button.y + button.h < 800.0);
+    }
+
+    #[test]
+    fn toolbar_buttons_sit_in_the_status_bar() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        let bar = layout.status_bar_rect();
+        let (resign, draw) = layout.toolbar_buttons();
+        for button in [resign, draw] {
+            assert!(button.y > bar.y && button.bottom() < bar.bottom(), "{button:?}");
+        }
+        assert!(resign.right() < draw.x, "resign is left of offer draw");
+        assert!(draw.right() > 790.0, "flush with the right edge");
+    }
+
+    #[test]
+    fn steps_follow_the_screen() {
+        let pos = |s: &str| Position::parse(s).unwrap();
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        assert_eq!(layout.step(pos("e2"), 0, -1), Some(pos("e3")), "up the screen is up the board");
+        assert_eq!(layout.step(pos("e2"), 1, 0), Some(pos("f2")));
+        assert_eq!(layout.step(pos("a1"), -1, 0), None);
+        assert_eq!(layout.step(pos("h8"), 0, -1), None);
+
+        let flipped = layout.with_flipped(true);
+        assert_eq!(flipped.step(pos("e7"), 0, -1), Some(pos("e6")), "Black's side is at the bottom");
+        assert_eq!(flipped.step(pos("e7"), 1, 0), Some(pos("d7")));
+        assert_eq!(flipped.step(pos("a1"), 0, -1), None, "a1 is at the top");
+    }
+
+    #[test]
+    fn flip_and_playing_black_compose() {
+        assert!(!black_at_bottom(false, None));
+        assert!(!black_at_bottom(false, Some(Color::White)));
+        assert!(black_at_bottom(false, Some(Color::Black)));
+        assert!(black_at_bottom(true, None));
+        assert!(black_at_bottom(true, Some(Color::White)));
+        assert!(black_at_bottom(true, Some(Color::Black)), "--flip doesn't turn Black's board back");
+    }
+
+    #[test]
+    fn promotion_tiles_run_down_the_promoting_file() {
+        let layout = Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT);
+        let a_file = Position::parse("a8").unwrap().column;
+        let tiles = layout.promotion_tiles(a_file, Color::White);
+        let expected: [Rect; 4] = std::array::from_fn(|i| Rect::new(0.0, i as f32 * 100.0, 100.0, 100.0));
+        assert_eq!(tiles, expected, "queen first, at the promotion square");
+
+        let h_file = Position::parse("h1").unwrap().column;
+        let tiles = layout.promotion_tiles(h_file, Color::Black);
+        let expected: [Rect; 4] = std::array::from_fn(|i| Rect::new(700.0, 700.0 - i as f32 * 100.0, 100.0, 100.0));
+        assert_eq!(tiles, expected, "Black's strip runs up from the bottom");
+
+        let flipped = layout.with_flipped(true);
+        assert_eq!(flipped.promotion_tiles(a_file, Color::White)[0], Rect::new(700.0, 700.0, 100.0, 100.0));
+        assert_eq!(flipped.promotion_tiles(a_file, Color::White)[3], Rect::new(700.0, 400.0, 100.0, 100.0));
+    }
+
+    #[test]
+    fn promotion_tiles_stay_on_screen() {
+        // A board scrolled partly above the window
+        let layout = Layout { origin: [0.0, -150.0], ..Layout::new(800.0, 800.0 + STATUS_BAR_HEIGHT) };
+        let tiles = layout.promotion_tiles(Position::parse("d8").unwrap().column, Color::White);
+        assert_eq!(tiles[0], Rect::new(300.0, 0.0, 100.0, 100.0));
+        assert_eq!(tiles[3], Rect::new(300.0, 300.0, 100.0, 100.0));
+    }
+}
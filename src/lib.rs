@@ -0,0 +1,7 @@
+// The chess protocol and its TCP transport, without any of the GUI, so other
+// programs can talk to this one
+pub mod protocol;
+pub mod network;
+
+pub use network::{connect_with_retries, listen, read_message, send_message, NetError, ReadBuffer, SendBuffer, SendStatus};
+pub use protocol::{parse, serialize, Message, MessageMove, MessageResync, ParseError, SerializeError};
@@ -1,47 +1,317 @@
+use std::io::ErrorKind;
 use std::net::{TcpListener, TcpStream};
-use std::{env, mem};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::{io, thread};
+use std::time::Duration;
+use std::{env, fs};
 use std::collections::HashMap;
 
-pub mod protocol;
-pub mod network;
+use log::{error, info, warn};
+
+pub mod options;
+pub mod layout;
+pub mod rules;
+pub mod replay;
+pub mod state;
+pub mod notation;
+pub mod save;
+pub mod ai;
+pub mod pgn;
+pub mod clock;
+pub mod theme;
+pub mod controls;
 
 use ggez::{
     Context, ContextBuilder, GameResult,
+    audio::{self, SoundSource},
     event::{self, EventHandler},
     graphics::{self, Image, Drawable},
+    input::keyboard::{KeyInput, PhysicalKey},
     input::mouse::MouseButton,
 };
 
 use rsoderh_chess::*;
 
-use crate::network::{read_message, send_message, NetError};
-use crate::protocol::{Message, MessageMove};
+use puhl_gui::network::{bind_error_message, connect_with_retries, lan_address, listen, NetError, ReadBuffer, SendBuffer};
+use puhl_gui::protocol::{board_diff, Message, MessageMove, MessageResync, MoveType, ParseError};
+use crate::options::{check_resources, parse_mode, parse_options, resource_dir, Filter, Mode, Options, Rotate, RESOURCES_ENV, USAGE};
+use crate::layout::{black_at_bottom, Layout, FILES, RANKS, STATUS_BAR_HEIGHT};
+use crate::rules::{game_state, result_line, DrawReason, Outcome};
+use crate::state::{castling_rook, promotion_rank, square_name, Applied, ChessState, Click, RemoteMoveError};
+use crate::replay::replay;
+use crate::save::{load_game, save_game, SAVE_FILE};
+use crate::ai::{choose_move, random_move, Rng};
+use crate::pgn::{from_pgn, to_pgn, PGN_FILE};
+use crate::theme::Theme;
+use crate::controls::{action_for, help_lines, Action};
+use crate::clock::{format_clock, format_spent, Clock, TimeSpent};
 
-const SCREEN_WIDTH: f32 = 800.0;
-const SCREEN_HEIGHT: f32 = 800.0;
-const FILES: usize = 8;
-const RANKS: usize = 8;
-const SQUARE_SIZE: f32 = SCREEN_WIDTH / FILES as f32;
+const DEFAULT_WINDOW_SIZE: (f32, f32) = (800.0, 800.0 + STATUS_BAR_HEIGHT);
+const ANIMATION_DURATION: Duration = Duration::from_millis(150);
+const REJECT_DURATION: Duration = Duration::from_millis(300);
+// Dots in the spinner on the waiting screen, and how long each one leads
+const SPINNER_DOTS: usize = 8;
+const SPINNER_STEP: Duration = Duration::from_millis(100);
+// How long `--demo` shows a finished game before starting the next
+const DEMO_GAME_OVER_PAUSE: Duration = Duration::from_secs(3);
+// How often and how far apart a client tries to get back to the host after the
+// connection drops
+const RECONNECT_ATTEMPTS: u32 = 10;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// Corrupt frames in a row from the opponent before the connection is given up
+const MAX_CORRUPT_FRAMES: u32 = 3;
+const PROMOTION_CHOICES: [PieceKind; 4] = [PieceKind::Queen, PieceKind::Rook, PieceKind::Bishop, PieceKind::Knight];
+const DEFAULT_LIGHT: [u8; 3] = [0xcc, 0xcc, 0xcc];
+const DEFAULT_DARK: [u8; 3] = [0x7c, 0x7c, 0x7c];
+const DEFAULT_SELECT: [u8; 4] = [0xf5, 0xf5, 0xdc, 0x80];
+const DEFAULT_MOVE: [u8; 4] = [0xa6, 0x7b, 0x5b, 0x80];
 
-// Represents the current UI state, so either playing or promoting
+// Represents the current UI state, so either playing, promoting, confirming a reset
+// or answering the opponent's takeback or rematch request
 #[derive(Clone, Copy)]
 enum UIState {
     Normal,
-    Promotion { column: PositionIndex, color: Color },
+    Promotion { source: Position, column: PositionIndex, color: Color },
+    ConfirmReset,
+    TakebackPrompt,
+    RematchPrompt,
+}
+
+// A piece sliding from its origin square to its destination after a move
+struct MoveAnimation {
+    piece: Piece,
+    src: Position,
+    dest: Position,
+    // The rook's squares when the move castles, so it slides along with the king
+    rook: Option<(Position, Position)>,
+    elapsed: Duration,
+}
+
+// A red flash and shake on the selected square after clicking an illegal destination
+struct Rejection {
+    position: Position,
+    elapsed: Duration,
+}
+
+// Sound effects, in increasing order of priority
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum SoundKind {
+    Move,
+    Capture,
+    Check,
+}
+
+// Loaded sound effects; a missing file just leaves that effect silent
+struct Sounds {
+    mv: Option<audio::Source>,
+    capture: Option<audio::Source>,
+    check: Option<audio::Source>,
+}
+
+impl Sounds {
+    fn load(ctx: &Context, mute: bool) -> Self {
+        let load = |name: &str| {
+            if mute {
+                return None;
+            }
+            let path = format!("/sounds/{}.wav", name);
+            match audio::Source::new(ctx, &path) {
+                Ok(source) => Some(source),
+                Err(e) => {
+                    warn!("Couldn't load sound '{}': {}", path, e);
+                    None
+                }
+            }
+        };
+
+        Self {
+            mv: load("move"),
+            capture: load("capture"),
+            check: load("check"),
+        }
+    }
+
+    fn play(&mut self, ctx: &Context, kind: SoundKind) {
+        let source = match kind {
+            SoundKind::Move => &mut self.mv,
+            SoundKind::Capture => &mut self.capture,
+            SoundKind::Check => &mut self.check,
+        };
+        if let Some(source) = source
+            && let Err(e) = source.play_detached(ctx)
+        {
+            warn!("Couldn't play sound: {}", e);
+        }
+    }
+}
+
+// Which of the network game's status bar buttons can be clicked
+#[derive(Clone, Copy, Default)]
+struct Toolbar {
+    resign: bool,
+    draw: bool,
+}
+
+// Local analysis markings made with the right mouse button
+#[derive(Clone, Copy, PartialEq)]
+enum Annotation {
+    Square(Position),
+    Arrow(Position, Position),
+}
+
+// `highlight`, or black/white at the same opacity if it would blend into `background`
+fn readable_on(highlight: graphics::Color, background: graphics::Color) -> graphics::Color {
+    let distance = ((highlight.r - background.r).powi(2)
+        + (highlight.g - background.g).powi(2)
+        + (highlight.b - background.b).powi(2))
+    .sqrt();
+    if distance >= 0.15 {
+        return highlight;
+    }
+
+    let luminance = 0.299 * background.r + 0.587 * background.g + 0.114 * background.b;
+    let base = if luminance > 0.5 { graphics::Color::BLACK } else { graphics::Color::WHITE };
+    graphics::Color { a: highlight.a, ..base }
+}
+
+// Log every square where the opponent's board differs from ours, for debugging desyncs
+fn log_board_diff(theirs: &Board, ours: &Board) {
+    let slot_name = |slot: Slot| match slot {
+        Slot::Empty => "empty".to_string(),
+        Slot::Occupied(piece) => format!("{:?} {:?}", piece.color, piece.kind),
+    };
+    for (position, their_slot, our_slot) in board_diff(theirs, ours) {
+        error!("{}: opponent has {}, we have {}", square_name(position), slot_name(their_slot), slot_name(our_slot));
+    }
+}
+
+// Draw the pieces of any `board`, not just the live game's, on the squares of
+// `layout`, which sets their size and the board's orientation. Squares where
+// `skip` is true are left out, for pieces the caller draws itself.
+fn draw_board_pieces(
+    canvas: &mut graphics::Canvas,
+    board: &Board,
+    layout: &Layout,
+    images: &HashMap<Piece, Image>,
+    skip: impl Fn(Position) -> bool,
+) {
+    for rank in 0..8 {
+        for file in 0..8 {
+            let position = Position::new(file, rank).unwrap();
+            if skip(position) {
+                continue;
+            }
+            if let Slot::Occupied(piece) = board.at_position(position) {
+                draw_piece_image(canvas, images, layout.square_size, piece, layout.square_origin(position));
+            }
+        }
+    }
+}
+
+// Draw `piece` scaled to fill a square of `size` pixels at `dest`
+fn draw_piece_image(
+    canvas: &mut graphics::Canvas,
+    images: &HashMap<Piece, Image>,
+    size: f32,
+    piece: Piece,
+    dest: [f32; 2],
+) {
+    if let Some(img) = images.get(&piece) {
+        let scale = [size / img.width() as f32, size / img.height() as f32];
+        canvas.draw(img, graphics::DrawParam::new().dest(dest).scale(scale));
+    }
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> graphics::Color {
+    graphics::Color::from_rgb(r, g, b)
+}
+
+fn rgba([r, g, b, a]: [u8; 4]) -> graphics::Color {
+    graphics::Color::from_rgba(r, g, b, a)
+}
+
+// Draw `msg` centered on `center`
+fn draw_centered_text(
+    canvas: &mut graphics::Canvas,
+    ctx: &Context,
+    msg: &str,
+    size: f32,
+    center: [f32; 2],
+    color: graphics::Color,
+) {
+    let text = graphics::Text::new(graphics::TextFragment {
+        text: msg.to_string(),
+        scale: Some(graphics::PxScale::from(size)),
+        ..Default::default()
+    });
+    let dims = text.dimensions(ctx);
+    let dest = [center[0] - dims.w / 2.0, center[1] - dims.h / 2.0];
+    canvas.draw(&text, graphics::DrawParam::new().dest(dest).color(color));
 }
 
 // Board state
 struct GUIBoard {
     pieces_img_map: HashMap<Piece, Image>,
     selected_position: Option<Position>,
-    game: Game,
-    winner: Option<Color>,
+    state: ChessState,
     ui_state: UIState,
+    animation: Option<MoveAnimation>,
+    rejection: Option<Rejection>,
+    sounds: Sounds,
+    queued_sound: Option<SoundKind>,
+    annotations: Vec<Annotation>,
+    annotation_start: Option<Position>,
+    hovered_position: Option<Position>,
+    // Square picked with the arrow keys, shown until Escape or a left-click
+    key_cursor: Option<Position>,
+    // Last known mouse position, which the wheel zooms around
+    cursor: [f32; 2],
+    // Set while the middle button drags the board around
+    panning: bool,
+    light_color: graphics::Color,
+    dark_color: graphics::Color,
+    // Highlights of the selected square and of its legal moves
+    select_color: graphics::Color,
+    move_color: graphics::Color,
+    theme: Theme,
+    // Tint the selected square and its legal moves; selecting works either way
+    show_highlights: bool,
+    // Outline pieces pinned to their king
+    show_pins: bool,
+    // The controls overlay, opened with F1 or ?
+    show_help: bool,
+    // Name the square under the mouse in a tooltip
+    show_square_names: bool,
+    // Lines between the squares and around the board
+    show_grid: bool,
+    // Lists the F12 position dump in the help
+    debug: bool,
+    clock: Option<Clock>,
+    // Shown instead of the clocks when there are none
+    time_spent: TimeSpent,
+    // Write each move's thinking time into the PGN
+    pgn_times: bool,
+    filter: Filter,
+    // Earlier position being looked at with the arrow keys, as a ply count;
+    // None shows the live game
+    viewing: Option<usize>,
+    error_message: Option<String>,
+    // Latest event, shown in the status bar
+    status: String,
+    // Shown over the board until the network opponent has connected
+    waiting_message: Option<String>,
+    // Resign and Offer Draw buttons, only shown in network games
+    toolbar: Option<Toolbar>,
+    // The network opponent is to move, so the board is dimmed
+    opponent_thinking: bool,
+    layout: Layout,
 }
 
 impl GUIBoard {
-    fn new(ctx: &mut Context) -> Self {
+    fn new(ctx: &mut Context, options: &Options) -> Self {
         let mut pieces_img_map = HashMap::new();
+        let (width, height) = ctx.gfx.drawable_size();
+        let mut status = String::new();
 
         let piece_assets = [
             (Color::White, PieceKind::Pawn, "white-pawn"),
@@ -60,35 +330,174 @@ impl GUIBoard {
 
         for (color, kind, name) in piece_assets {
             let piece = Piece { color, kind };
-            let path = format!("/pieces/{}.png", name);
-            let img = Image::from_path(ctx, path).unwrap();
+            let themed = options.pieces.as_ref().and_then(|dir| {
+                let path = dir.join(format!("{}.png", name));
+                let img = fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|bytes| Image::from_bytes(ctx, &bytes).map_err(|e| e.to_string()));
+                match img {
+                    Ok(img) => Some(img),
+                    Err(e) => {
+                        status = format!("Couldn't load {} from '{}', using the default piece: {}", name, path.display(), e);
+                        warn!("{}", status);
+                        None
+                    }
+                }
+            });
+            let img = match themed {
+                Some(img) => img,
+                None => Image::from_path(ctx, format!("/pieces/{}.png", name)).unwrap(),
+            };
             pieces_img_map.insert(piece, img);
         }
 
         Self {
             pieces_img_map,
             selected_position: None,
-            game: Game::new_standard(),
-            winner: None,
+            state: ChessState::default(),
             ui_state: UIState::Normal,
+            animation: None,
+            rejection: None,
+            sounds: Sounds::load(ctx, options.mute),
+            queued_sound: None,
+            annotations: Vec::new(),
+            annotation_start: None,
+            hovered_position: None,
+            key_cursor: None,
+            cursor: [0.0, 0.0],
+            panning: false,
+            light_color: rgb(options.light.unwrap_or(DEFAULT_LIGHT)),
+            dark_color: rgb(options.dark.unwrap_or(DEFAULT_DARK)),
+            select_color: rgba(options.select_color.unwrap_or(DEFAULT_SELECT)),
+            move_color: rgba(options.move_color.unwrap_or(DEFAULT_MOVE)),
+            theme: Theme::new(options.theme),
+            show_highlights: !options.no_highlights,
+            show_pins: options.pins,
+            show_help: false,
+            show_square_names: options.square_names,
+            show_grid: options.grid,
+            debug: options.debug,
+            clock: options.clock.map(Clock::new),
+            time_spent: TimeSpent::default(),
+            pgn_times: options.pgn_times,
+            filter: options.filter,
+            viewing: None,
+            error_message: None,
+            waiting_message: None,
+            toolbar: None,
+            opponent_thinking: false,
+            status,
+            layout: Layout::new(width, height),
         }
     }
 
     // Reset game to initial state
     fn reset(&mut self) {
-        self.game = Game::new_standard();
-        self.winner = None;
+        self.state = ChessState::default();
         self.selected_position = None;
+        self.viewing = None;
         self.ui_state = UIState::Normal;
+        self.animation = None;
+        self.annotations.clear();
+        if let Some(clock) = self.clock.as_mut() {
+            clock.reset();
+        }
+        self.time_spent = TimeSpent::default();
+    }
+
+    // Add an annotation, or remove it if it is already drawn
+    fn toggle_annotation(&mut self, annotation: Annotation) {
+        if let Some(i) = self.annotations.iter().position(|a| *a == annotation) {
+            self.annotations.remove(i);
+        } else {
+            self.annotations.push(annotation);
+        }
+    }
+
+    // Start sliding the piece that just landed on `dest` from `src`
+    fn start_animation(&mut self, src: Position, dest: Position, move_type: MoveType) {
+        let Slot::Occupied(piece) = self.state.game.board().at_position(dest) else { return };
+        self.animation = Some(MoveAnimation {
+            piece,
+            src,
+            dest,
+            rook: castling_rook(move_type, dest),
+            elapsed: Duration::ZERO,
+        });
+    }
+
+    // Advance the running animation, dropping it once it has finished
+    fn advance_animation(&mut self, dt: Duration) {
+        if let Some(rejection) = self.rejection.as_mut() {
+            rejection.elapsed += dt;
+            if rejection.elapsed >= REJECT_DURATION {
+                self.rejection = None;
+            }
+        }
+
+        let Some(animation) = self.animation.as_mut() else { return };
+        animation.elapsed += dt;
+        if animation.elapsed >= ANIMATION_DURATION {
+            self.animation = None;
+        }
+    }
+
+    // Flash the piece on `position` to show a move from it wasn't accepted
+    fn reject(&mut self, position: Position) {
+        self.rejection = Some(Rejection { position, elapsed: Duration::ZERO });
+    }
+
+    // Progress of the rejection flash on `position` from 0 to 1, if it is flashing
+    fn rejection_progress(&self, position: Position) -> Option<f32> {
+        let rejection = self.rejection.as_ref().filter(|rejection| rejection.position == position)?;
+        Some((rejection.elapsed.as_secs_f32() / REJECT_DURATION.as_secs_f32()).min(1.0))
+    }
+
+    // Queue a sound for the next update, keeping the most important one
+    fn queue_sound(&mut self, kind: SoundKind) {
+        if self.queued_sound.is_none_or(|queued| queued < kind) {
+            self.queued_sound = Some(kind);
+        }
+    }
+
+    // Play the queued sound, if any
+    fn play_queued_sound(&mut self, ctx: &Context) {
+        if let Some(kind) = self.queued_sound.take() {
+            self.sounds.play(ctx, kind);
+        }
+    }
+
+    // Sound for a move that was just applied
+    fn move_sound(capture: bool, check: bool) -> SoundKind {
+        if check {
+            SoundKind::Check
+        } else if capture {
+            SoundKind::Capture
+        } else {
+            SoundKind::Move
+        }
     }
 
     // Draw the full board and overlays
     fn draw(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
         self.draw_squares(canvas);
-        self.draw_highlights(canvas);
+        self.draw_highlights(canvas, ctx);
+        self.draw_en_passant(canvas, ctx);
+        self.draw_rejection(canvas);
         self.draw_pieces(canvas);
+        self.draw_pins(canvas, ctx);
+        self.draw_hover(canvas, ctx);
+        self.draw_key_cursor(canvas, ctx);
+        self.draw_annotations(canvas, ctx);
+        self.draw_thinking_overlay(canvas, ctx);
+        self.draw_status_bar(canvas, ctx);
         self.draw_promotion_overlay(canvas);
-        self.draw_winner_banner(canvas, ctx);
+        self.draw_outcome_banner(canvas, ctx);
+        self.draw_prompt_overlay(canvas, ctx);
+        self.draw_waiting_overlay(canvas, ctx);
+        self.draw_error_overlay(canvas, ctx);
+        self.draw_help(canvas, ctx);
+        self.draw_square_name(canvas, ctx);
     }
 
     // Draw board squares
@@ -96,143 +505,411 @@ impl GUIBoard {
         for rank in 0..RANKS {
             for file in 0..FILES {
                 let is_black = (rank + file) % 2 == 1;
-                let color = if is_black {
-                    graphics::Color::from_rgb(0x7c, 0x7c, 0x7c)
-                } else {
-                    graphics::Color::from_rgb(0xcc, 0xcc, 0xcc)
-                };
+                let color = if is_black { self.dark_color } else { self.light_color };
 
-                let rect = graphics::Rect::new(
-                    file as f32 * SQUARE_SIZE,
-                    rank as f32 * SQUARE_SIZE,
-                    SQUARE_SIZE,
-                    SQUARE_SIZE,
-                );
+                let position = Position::new(file as u8, (RANKS - 1 - rank) as u8).unwrap();
+                let rect = self.layout.square_rect(position);
                 canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(rect).color(color));
             }
         }
+
+        if !self.show_grid {
+            return;
+        }
+        // About a pixel at the default size, thicker as the squares grow. The
+        // outermost lines frame the board.
+        let board = self.layout.board_rect();
+        let square_size = self.layout.square_size;
+        let width = (square_size / 100.0).max(1.0);
+        for i in 0..=FILES {
+            let x = board.x + i as f32 * square_size - width / 2.0;
+            let line = graphics::Rect::new(x, board.y - width / 2.0, width, board.h + width);
+            canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(line).color(self.theme.grid));
+        }
+        for i in 0..=RANKS {
+            let y = board.y + i as f32 * square_size - width / 2.0;
+            let line = graphics::Rect::new(board.x - width / 2.0, y, board.w + width, width);
+            canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(line).color(self.theme.grid));
+        }
+    }
+
+    // Background color of a square
+    fn square_color(&self, position: Position) -> graphics::Color {
+        if (position.column() + position.row()).is_multiple_of(2) {
+            self.dark_color
+        } else {
+            self.light_color
+        }
     }
 
     // Draw selection and valid move highlights
-    fn draw_highlights(&self, canvas: &mut graphics::Canvas) {
+    fn draw_highlights(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        if !self.show_highlights || self.viewing.is_some() {
+            return;
+        }
         let Some(src_position) = self.selected_position else { return };
+        // A stale selection, e.g. of the piece that just moved, gets no highlights
+        if !self.state.movable(src_position) {
+            return;
+        }
 
         // Selected square
-        let rect = graphics::Rect::new(
-            src_position.column() as f32 * SQUARE_SIZE,
-            (7 - src_position.row()) as f32 * SQUARE_SIZE,
-            SQUARE_SIZE,
-            SQUARE_SIZE,
-        );
+        let rect = self.layout.square_rect(src_position);
         canvas.draw(
             &graphics::Quad,
             graphics::DrawParam::new()
                 .dest_rect(rect)
-                .color(graphics::Color::from_rgba(0xF5, 0xF5, 0xDC, 128)),
+                .color(readable_on(self.select_color, self.square_color(src_position))),
         );
 
-        // Valid moves
-        if let Some(valid_moves) = self.game.valid_moves(src_position) {
-            for pos in valid_moves.iter() {
-                let rect = graphics::Rect::new(
-                    pos.column() as f32 * SQUARE_SIZE,
-                    (7 - pos.row()) as f32 * SQUARE_SIZE,
-                    SQUARE_SIZE,
-                    SQUARE_SIZE,
-                );
-                canvas.draw(
-                    &graphics::Quad,
-                    graphics::DrawParam::new()
-                        .dest_rect(rect)
-                        .color(graphics::Color::from_rgba(0xA6, 0x7B, 0x5B, 128)),
-                );
+        // Valid moves: a dot for quiet moves, a ring around capturable pieces
+        let square_size = self.layout.square_size;
+        // Cached when the piece was selected
+        if let Some(valid_moves) = self.state.cached_moves(src_position) {
+            for &pos in valid_moves {
+                let color = readable_on(self.move_color, self.square_color(pos));
+                let mesh = match self.state.game.board().at_position(pos) {
+                    Slot::Occupied(_) => graphics::Mesh::new_circle(
+                        ctx,
+                        graphics::DrawMode::stroke(square_size * 0.08),
+                        self.layout.square_center(pos),
+                        square_size * 0.46,
+                        0.5,
+                        color,
+                    ),
+                    Slot::Empty => graphics::Mesh::new_circle(
+                        ctx,
+                        graphics::DrawMode::fill(),
+                        self.layout.square_center(pos),
+                        square_size * 0.15,
+                        0.5,
+                        color,
+                    ),
+                };
+                if let Ok(mesh) = mesh {
+                    canvas.draw(&mesh, graphics::DrawParam::new());
+                }
+            }
+        }
+    }
+
+    // Faint ring on the square a pawn just skipped, which can be captured en
+    // passant on this move. Shown with the move highlights.
+    fn draw_en_passant(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        if !self.show_highlights || self.viewing.is_some() {
+            return;
+        }
+        let Some(target) = self.state.en_passant() else { return };
+        let square_size = self.layout.square_size;
+        let color = graphics::Color::from_rgba(0x3A, 0x8C, 0xD9, 110);
+        if let Ok(mesh) = graphics::Mesh::new_circle(
+            ctx,
+            graphics::DrawMode::stroke(square_size * 0.04),
+            self.layout.square_center(target),
+            square_size * 0.3,
+            0.5,
+            color,
+        ) {
+            canvas.draw(&mesh, graphics::DrawParam::new());
+        }
+    }
+
+    // Outline the pieces pinned to their king, as worked out before drawing
+    fn draw_pins(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        if !self.show_pins || self.viewing.is_some() {
+            return;
+        }
+        let Some(pinned) = self.state.cached_pins() else { return };
+        let color = graphics::Color::from_rgba(0xD9, 0x3A, 0x8C, 200);
+        let width = self.layout.square_size * 0.05;
+        for &position in pinned {
+            let square = self.layout.square_rect(position);
+            // Keep the whole border inside the square
+            let rect = graphics::Rect::new(square.x + width / 2.0, square.y + width / 2.0, square.w - width, square.h - width);
+            if let Ok(mesh) = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(width), rect, color) {
+                canvas.draw(&mesh, graphics::DrawParam::new());
             }
         }
     }
 
+    // Tint the rejected square red, fading out
+    fn draw_rejection(&self, canvas: &mut graphics::Canvas) {
+        let Some(rejection) = &self.rejection else { return };
+        let Some(t) = self.rejection_progress(rejection.position) else { return };
+        let color = graphics::Color::new(0.85, 0.1, 0.1, 0.6 * (1.0 - t));
+        let rect = self.layout.square_rect(rejection.position);
+        canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(rect).color(color));
+    }
+
     // Draw chess pieces
     fn draw_pieces(&self, canvas: &mut graphics::Canvas) {
-        for rank in 0..8 {
-            for file in 0..8 {
-                let slot = self.game.board().at_position(Position::new(file, rank).unwrap());
-                if let Slot::Occupied(piece) = slot {
-                    if let Some(img) = self.pieces_img_map.get(&piece) {
-                        let dest_x = file as f32 * SQUARE_SIZE;
-                        let dest_y = (7 - rank) as f32 * SQUARE_SIZE;
-
-                        let scale = [
-                            SQUARE_SIZE / img.width() as f32,
-                            SQUARE_SIZE / img.height() as f32,
-                        ];
-
-                        canvas.draw(img, graphics::DrawParam::new().dest([dest_x, dest_y]).scale(scale));
+        let viewed = self.viewing.and_then(|ply| self.state.board_at(ply));
+        let board = viewed.unwrap_or(self.state.board());
+        // Animations belong to the live game
+        let animation = self.animation.as_ref().filter(|_| viewed.is_none());
+
+        // The animated and shaking pieces are drawn separately below
+        let rejected = self.rejection.as_ref().map(|rejection| rejection.position).filter(|_| viewed.is_none());
+        draw_board_pieces(canvas, board, &self.layout, &self.pieces_img_map, |position| {
+            Some(position) == rejected
+                || animation.is_some_and(|animation| {
+                    animation.dest == position || animation.rook.is_some_and(|(_, rook_dest)| rook_dest == position)
+                })
+        });
+
+        if let Some(position) = rejected
+            && let Slot::Occupied(piece) = board.at_position(position)
+            && let Some(t) = self.rejection_progress(position)
+        {
+            // A rejected piece shakes sideways, settling as the flash fades
+            let [mut x, y] = self.layout.square_origin(position);
+            x += (t * 6.0 * std::f32::consts::PI).sin() * self.layout.square_size * 0.08 * (1.0 - t);
+            self.draw_piece(canvas, piece, [x, y]);
+        }
+
+        if let Some(animation) = animation {
+            let t = (animation.elapsed.as_secs_f32() / ANIMATION_DURATION.as_secs_f32()).min(1.0);
+            let start = self.layout.square_origin(animation.src);
+            let end = self.layout.square_origin(animation.dest);
+            let lerp = |start: [f32; 2], end: [f32; 2]| {
+                [start[0] + (end[0] - start[0]) * t, start[1] + (end[1] - start[1]) * t]
+            };
+            self.draw_piece(canvas, animation.piece, lerp(start, end));
+            if let Some((rook_src, rook_dest)) = animation.rook {
+                let rook = Piece { color: animation.piece.color, kind: PieceKind::Rook };
+                let start = self.layout.square_origin(rook_src);
+                let end = self.layout.square_origin(rook_dest);
+                self.draw_piece(canvas, rook, lerp(start, end));
+            }
+        }
+    }
+
+    // Draw a single piece scaled to fill a square at `dest`
+    fn draw_piece(&self, canvas: &mut graphics::Canvas, piece: Piece, dest: [f32; 2]) {
+        draw_piece_image(canvas, &self.pieces_img_map, self.layout.square_size, piece, dest);
+    }
+
+    // Draw a faint outline on the square under the cursor
+    fn draw_hover(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let Some(position) = self.hovered_position else { return };
+        if matches!(self.ui_state, UIState::Promotion { .. }) || self.state.outcome.is_some() {
+            return;
+        }
+
+        let rect = self.layout.square_rect(position);
+        let color = graphics::Color::from_rgba(0xFF, 0xFF, 0xFF, 110);
+        if let Ok(outline) = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(3.0), rect, color) {
+            canvas.draw(&outline, graphics::DrawParam::new());
+        }
+    }
+
+    // Draw a thick outline on the keyboard cursor's square, in a color unlike
+    // the hover outline
+    fn draw_key_cursor(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let Some(position) = self.key_cursor else { return };
+        let rect = self.layout.square_rect(position);
+        let color = graphics::Color::from_rgba(0xF2, 0xC1, 0x2E, 230);
+        let width = 0.06 * self.layout.square_size;
+        if let Ok(outline) = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(width), rect, color) {
+            canvas.draw(&outline, graphics::DrawParam::new());
+        }
+    }
+
+    // Name the square under the mouse in a small tooltip next to the cursor.
+    // Hidden under every overlay and once the game is over.
+    fn draw_square_name(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let Some(position) = self.hovered_position.filter(|_| self.show_square_names) else { return };
+        if !matches!(self.ui_state, UIState::Normal)
+            || self.state.outcome.is_some()
+            || self.show_help
+            || self.waiting_message.is_some()
+            || self.error_message.is_some()
+        {
+            return;
+        }
+
+        let size = 0.25 * self.layout.square_size;
+        let text = graphics::Text::new(graphics::TextFragment {
+            text: square_name(position),
+            scale: Some(graphics::PxScale::from(size)),
+            ..Default::default()
+        });
+        let dims = text.dimensions(ctx);
+        let padding = 0.25 * size;
+        let mut rect = graphics::Rect::new(
+            self.cursor[0] + size,
+            self.cursor[1] + size,
+            dims.w + 2.0 * padding,
+            dims.h + 2.0 * padding,
+        );
+        // Keep it on the board near the right and bottom edges
+        let board = self.layout.board_rect();
+        if rect.right() > board.right() {
+            rect.x = self.cursor[0] - size - rect.w;
+        }
+        if rect.bottom() > board.bottom() {
+            rect.y = self.cursor[1] - size - rect.h;
+        }
+        canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(rect).color(self.theme.panel));
+        canvas.draw(
+            &text,
+            graphics::DrawParam::new().dest([rect.x + padding, rect.y + padding]).color(self.theme.panel_text),
+        );
+    }
+
+    // Draw right-click square highlights and arrows
+    fn draw_annotations(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let color = graphics::Color::from_rgba(0xE0, 0x6C, 0x1A, 170);
+        let square_size = self.layout.square_size;
+
+        for annotation in &self.annotations {
+            match *annotation {
+                Annotation::Square(position) => {
+                    let rect = self.layout.square_rect(position);
+                    canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(rect).color(color));
+                }
+                Annotation::Arrow(from, to) => {
+                    let [sx, sy] = self.layout.square_center(from);
+                    let [ex, ey] = self.layout.square_center(to);
+                    let len = ((ex - sx).powi(2) + (ey - sy).powi(2)).sqrt();
+                    let (ux, uy) = ((ex - sx) / len, (ey - sy) / len);
+
+                    // Shaft stops where the head begins so the tip stays sharp
+                    let head = square_size * 0.35;
+                    let base = [ex - ux * head, ey - uy * head];
+                    if let Ok(shaft) = graphics::Mesh::new_line(ctx, &[[sx, sy], base], square_size * 0.15, color) {
+                        canvas.draw(&shaft, graphics::DrawParam::new());
+                    }
+
+                    let half_width = head * 0.6;
+                    let tip = [
+                        [ex, ey],
+                        [base[0] - uy * half_width, base[1] + ux * half_width],
+                        [base[0] + uy * half_width, base[1] - ux * half_width],
+                    ];
+                    if let Ok(tip) = graphics::Mesh::new_polygon(ctx, graphics::DrawMode::fill(), &tip, color) {
+                        canvas.draw(&tip, graphics::DrawParam::new());
                     }
                 }
             }
         }
     }
 
+    // Draw the latest event along the bottom of the window, cut to one line
+    fn draw_status_bar(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let bar = self.layout.status_bar_rect();
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(bar)
+                .color(self.theme.status_bar),
+        );
+
+        let padding = bar.h / 4.0;
+        let text = |msg: String| {
+            graphics::Text::new(graphics::TextFragment {
+                text: msg,
+                scale: Some(graphics::PxScale::from(bar.h * 0.6)),
+                ..Default::default()
+            })
+        };
+        // Clocks, or the time spent without them, and fullmove number on the right, the status in the space left of them
+        let mut counter = format!("Move {}", self.state.fullmove());
+        if let Some(offered_by) = self.state.draw_offer {
+            let side = if offered_by == Color::White { "White" } else { "Black" };
+            counter = format!("{side} offers a draw   {counter}");
+        }
+        let (white, black) = match &self.clock {
+            Some(clock) => (format_clock(clock.remaining(Color::White)), format_clock(clock.remaining(Color::Black))),
+            None => (
+                format_spent(self.time_spent.spent(Color::White)),
+                format_spent(self.time_spent.spent(Color::Black)),
+            ),
+        };
+        counter = format!("White {white}   Black {black}   {counter}");
+        let mut right = bar.x + bar.w;
+        if let Some(toolbar) = self.toolbar {
+            let (resign, draw) = self.layout.toolbar_buttons();
+            for (button, label, enabled) in [(resign, "Resign", toolbar.resign), (draw, "Offer Draw", toolbar.draw)] {
+                let (fill, label_color) = if enabled {
+                    (self.theme.button, self.theme.button_text)
+                } else {
+                    (self.theme.button_disabled, self.theme.button_disabled_text)
+                };
+                canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(button).color(fill));
+                let center = [button.x + button.w / 2.0, button.y + button.h / 2.0];
+                draw_centered_text(canvas, ctx, label, bar.h * 0.5, center, label_color);
+            }
+            right = resign.x;
+        }
+        let counter = text(counter);
+        let counter_dims = counter.dimensions(ctx);
+        let counter_dest = [right - padding - counter_dims.w, bar.y + (bar.h - counter_dims.h) / 2.0];
+        canvas.draw(&counter, graphics::DrawParam::new().dest(counter_dest).color(self.theme.status_text));
+
+        let mut msg = self.status.clone();
+        let mut line = text(msg.clone());
+        while line.dimensions(ctx).w > right - bar.x - 3.0 * padding - counter_dims.w && msg.pop().is_some() {
+            line = text(format!("{}...", msg.trim_end()));
+        }
+
+        let dims = line.dimensions(ctx);
+        let dest = [bar.x + padding, bar.y + (bar.h - dims.h) / 2.0];
+        canvas.draw(&line, graphics::DrawParam::new().dest(dest).color(self.theme.status_text));
+    }
+
     // Draw promotion overlay
     fn draw_promotion_overlay(&self, canvas: &mut graphics::Canvas) {
-        if let UIState::Promotion { color, .. } = self.ui_state {
+        if let UIState::Promotion { column, color, .. } = self.ui_state {
             // Dim background
-            let dim_rect = graphics::Rect::new(0.0, 0.0, SCREEN_WIDTH, SCREEN_HEIGHT);
+            let dim_rect = self.layout.window_rect();
             canvas.draw(
                 &graphics::Quad,
                 graphics::DrawParam::new()
                     .dest_rect(dim_rect)
-                    .color(graphics::Color::from_rgba(0, 0, 0, 160)),
+                    .color(self.theme.dim),
             );
 
             // Promotion choices
-            let choices = [PieceKind::Queen, PieceKind::Rook, PieceKind::Bishop, PieceKind::Knight];
-            let start_x = SCREEN_WIDTH / 2.0 - 2.0 * SQUARE_SIZE;
-            let y = SCREEN_HEIGHT / 2.0 - SQUARE_SIZE / 2.0;
-
-            for (i, kind) in choices.iter().enumerate() {
-                let x = start_x + i as f32 * SQUARE_SIZE;
-
-                // Background tile
-                let tile = graphics::Rect::new(x, y, SQUARE_SIZE, SQUARE_SIZE);
-                canvas.draw(
-                    &graphics::Quad,
-                    graphics::DrawParam::new()
-                        .dest_rect(tile)
-                        .color(graphics::Color::from_rgba(240, 240, 240, 220)),
-                );
-
-                let piece = Piece { color, kind: *kind };
-                if let Some(img) = self.pieces_img_map.get(&piece) {
-                    let scale = [
-                        SQUARE_SIZE / img.width() as f32,
-                        SQUARE_SIZE / img.height() as f32,
-                    ];
-                    canvas.draw(img, graphics::DrawParam::new().dest([x, y]).scale(scale));
-                }
+            for (kind, tile) in PROMOTION_CHOICES.into_iter().zip(self.layout.promotion_tiles(column, color)) {
+                // Background tile, tinted for the promoting side
+                let tint = match color {
+                    Color::White => self.theme.promotion_tile_white,
+                    Color::Black => self.theme.promotion_tile_black,
+                };
+                canvas.draw(&graphics::Quad, graphics::DrawParam::new().dest_rect(tile).color(tint));
+
+                self.draw_piece(canvas, Piece { color, kind }, [tile.x, tile.y]);
             }
         }
     }
 
-    // Draw winner banner if game is finished
-    fn draw_winner_banner(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
-        let Some(winner) = self.winner else { return };
+    // Draw the result banner if the game is finished
+    fn draw_outcome_banner(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let Some(outcome) = self.state.outcome else { return };
 
-        let msg = match winner {
-            Color::White => "White wins!",
-            Color::Black => "Black wins!",
+        let (msg, reason) = match outcome {
+            Outcome::Checkmate(Color::White) => ("White wins!", None),
+            Outcome::Checkmate(Color::Black) => ("Black wins!", None),
+            Outcome::Timeout(Color::White) => ("White wins!", Some("on time")),
+            Outcome::Timeout(Color::Black) => ("Black wins!", Some("on time")),
+            Outcome::Resignation(Color::White) => ("White wins!", Some("by resignation")),
+            Outcome::Resignation(Color::Black) => ("Black wins!", Some("by resignation")),
+            Outcome::Draw(DrawReason::Repetition) => ("Draw!", Some("by threefold repetition")),
+            Outcome::Draw(DrawReason::FiftyMoves) => ("Draw!", Some("by the fifty-move rule")),
+            Outcome::Draw(DrawReason::InsufficientMaterial) => ("Draw!", Some("by insufficient material")),
+            Outcome::Draw(DrawReason::Agreement) => ("Draw!", Some("by agreement")),
         };
 
         let text = graphics::Text::new(graphics::TextFragment {
             text: msg.to_string(),
-            scale: Some(graphics::PxScale::from(120.0)),
+            scale: Some(graphics::PxScale::from(1.2 * self.layout.square_size)),
             ..Default::default()
         });
 
         let dims = text.dimensions(ctx);
-        let dest_point = [
-            SCREEN_WIDTH / 2.0 - dims.w as f32 / 2.0,
-            SCREEN_HEIGHT / 2.0 - dims.h as f32 / 2.0,
-        ];
+        let [center_x, center_y] = self.layout.board_center();
+        let dest_point = [center_x - dims.w / 2.0, center_y - dims.h / 2.0];
 
         // Outline
         let outline = 3.0;
@@ -244,7 +921,7 @@ impl GUIBoard {
                 &text,
                 graphics::DrawParam::new()
                     .dest([dest_point[0] + dx, dest_point[1] + dy])
-                    .color(graphics::Color::BLACK),
+                    .color(self.theme.banner_outline),
             );
         }
 
@@ -253,256 +930,1639 @@ impl GUIBoard {
             &text,
             graphics::DrawParam::new()
                 .dest(dest_point)
-                .color(graphics::Color::WHITE),
+                .color(self.theme.banner_text),
         );
-    }
 
-    // Replace game state and perform move
-    fn perform_move(&mut self, mv: HalfMoveRequest) {
-        let placeholder = Game::new(self.game.board().clone(), self.game.turn);
-        let game = mem::replace(&mut self.game, placeholder);
-        let result = game.perform_move(mv);
+        if let Some(reason) = reason {
+            let size = 0.4 * self.layout.square_size;
+            let center = [center_x, dest_point[1] + dims.h + size];
+            draw_centered_text(canvas, ctx, reason, size, center, self.theme.banner_text);
+        }
 
-        self.game = match result {
-            MoveResult::Ongoing(new_game, check) => {
-                println!("Check outcome: {:?}", check);
-                new_game
-            }
-            MoveResult::Finished(finished) => {
-                println!("Game over: {:?}", finished.result());
+        // The play-again prompt takes the button's place once it is open
+        if let UIState::Normal = self.ui_state {
+            let button = self.layout.save_pgn_button();
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest_rect(button)
+                    .color(self.theme.panel),
+            );
+            let center = [button.x + button.w / 2.0, button.y + button.h / 2.0];
+            draw_centered_text(canvas, ctx, "Save PGN", 0.35 * self.layout.square_size, center, self.theme.panel_text);
+        }
+    }
 
-                let rsoderh_chess::GameResult::Checkmate { winner, .. } = finished.result();
-                self.winner = Some(*winner);
+    // Write the game to the PGN file, reporting how it went in the status bar
+    fn save_pgn(&mut self) {
+        match fs::write(PGN_FILE, to_pgn(&self.state, self.pgn_times)) {
+            Ok(()) => self.set_status(format!("Saved the game to {PGN_FILE}")),
+            Err(err) => self.set_status(format!("Couldn't save {PGN_FILE}: {err}")),
+        }
+    }
 
-                Game::new(finished.board().clone(), self.game.turn)
-            }
-            MoveResult::Illegal(game, why) => {
-                println!("Illegal move: {:?}", why);
-                game
-            }
+    // Draw the yes/no prompt shown before resetting a finished game or taking
+    // back a move for the opponent
+    fn draw_prompt_overlay(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let question = match self.ui_state {
+            UIState::ConfirmReset => "Play again?",
+            UIState::TakebackPrompt => "Allow takeback?",
+            UIState::RematchPrompt => "Rematch?",
+            _ => return,
         };
-    }
-}
 
-// Main game container
-struct MyGame {
-    board: GUIBoard,
-    stream: Option<TcpStream>,
-    playing_as: Color,
-}
+        let square_size = self.layout.square_size;
+        let (yes, no) = self.layout.confirm_reset_buttons();
+        let panel = graphics::Rect::new(
+            yes.x - square_size / 4.0,
+            yes.y - square_size,
+            no.x + no.w - yes.x + square_size / 2.0,
+            yes.h + 1.25 * square_size,
+        );
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(panel)
+                .color(self.theme.dim),
+        );
+        draw_centered_text(
+            canvas,
+            ctx,
+            question,
+            0.4 * square_size,
+            [self.layout.board_center()[0], yes.y - square_size / 2.0],
+            self.theme.overlay_text,
+        );
 
-impl MyGame {
-    pub fn new(ctx: &mut Context, stream: Option<TcpStream>, playing_as: Color) -> Self {
-        Self { board: GUIBoard::new(ctx), stream, playing_as }
+        for (rect, label) in [(yes, "Yes"), (no, "No")] {
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest_rect(rect)
+                    .color(self.theme.panel),
+            );
+            draw_centered_text(
+                canvas,
+                ctx,
+                label,
+                0.36 * square_size,
+                [rect.x + rect.w / 2.0, rect.y + rect.h / 2.0],
+                self.theme.panel_text,
+            );
+        }
     }
-}
 
-impl EventHandler for MyGame {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult {
-        if self.board.game.turn == self.playing_as {
-            return Ok(());
+    // List the mouse and keyboard controls over the dimmed board
+    fn draw_help(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        if !self.show_help {
+            return;
         }
-        match self.stream.as_mut() {
-            Some(stream) => {
-                let message = read_message(stream);
-                match message {
-                    Ok(message) => {
-                        match message {
-                            Message::Move(message) => {
-                                let placeholder = Game::new(self.board.game.board().clone(), self.board.game.turn);
-                                let game = mem::replace(&mut self.board.game, placeholder);
-                                let result = match message.prom_piece {
-                                    Some(piece_kind) => game.perform_move(HalfMoveRequest::Promotion { column: message.mv.1.column, kind: piece_kind }),
-                                    None => game.perform_move(HalfMoveRequest::Standard { source: message.mv.0, dest: message.mv.1 }),
-                                };
-
-                                self.board.game = match result {
-                                    MoveResult::Ongoing(new_game, check) => {
-                                        println!("Check outcome: {:?}", check);
-                                        new_game
-                                    }
-                                    MoveResult::Finished(finished) => {
-                                        println!("Game over: {:?}", finished.result());
-
-                                        let rsoderh_chess::GameResult::Checkmate { winner, .. } = finished.result();
-                                        self.board.winner = Some(*winner);
-
-                                        Game::new(finished.board().clone(), self.board.game.turn)
-                                    }
-                                    MoveResult::Illegal(_game, why) => {
-                                        println!("Illegal move: {:?}", why);
-                                        let _ = send_message(stream, &Message::Quit("Desync".to_string()));
-                                        panic!("Board desync!!!");
-                                    }
-                                };
-                            },
-                            Message::Quit(s) => {
-                                panic!("Opponent quit: {s}");
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        match e {
-                            NetError::IoError(_e) => {},
-                            NetError::ParseError(e) => panic!("Failed to read opponent moves: {e:?}"),
-                            NetError::SerializeError(e) => panic!("Failed to read opponent moves: {e:?}"),
-                        }
-                    }
-                }
-            },
-            None => (),
-        };
-        Ok(())
-    }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        let mut canvas = graphics::Canvas::from_frame(ctx, graphics::Color::WHITE);
-        self.board.draw(&mut canvas, ctx);
-        canvas.finish(ctx)
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(self.layout.board_rect())
+                .color(self.theme.dim),
+        );
+        let board = self.layout.board_rect();
+        let size = 0.2 * self.layout.square_size;
+        let lines = help_lines(self.debug);
+        let spacing = 1.5 * size;
+        let top = board.y + board.h / 2.0 - spacing * (lines.len() + 2) as f32 / 2.0;
+        draw_centered_text(
+            canvas,
+            ctx,
+            "Controls",
+            1.5 * size,
+            [board.x + board.w / 2.0, top],
+            self.theme.overlay_text,
+        );
+        for (i, line) in lines.iter().enumerate() {
+            let text = graphics::Text::new(graphics::TextFragment {
+                text: line.clone(),
+                scale: Some(graphics::PxScale::from(size)),
+                ..Default::default()
+            });
+            let dest = [board.x + 0.05 * board.w, top + spacing * (i + 2) as f32];
+            canvas.draw(&text, graphics::DrawParam::new().dest(dest).color(self.theme.overlay_text));
+        }
+        draw_centered_text(
+            canvas,
+            ctx,
+            "Press F1 or click to close",
+            size,
+            [board.x + board.w / 2.0, top + spacing * (lines.len() + 3) as f32],
+            self.theme.faint_text,
+        );
     }
 
-    fn mouse_button_down_event(
-        &mut self,
-        _ctx: &mut Context,
-        button: MouseButton,
-        x: f32,
-        y: f32,
-    ) -> GameResult {
-        if button != MouseButton::Left {
-            return Ok(());
-        }
-        // Don't play if it's not your turn
-        if self.board.game.turn != self.playing_as {
-            return Ok(());
-        }
-        // Reset if game ended
-        if self.board.winner.is_some() {
-            self.board.reset();
-            return Ok(());
+    // Lightly dim the board while the network opponent is choosing a move
+    fn draw_thinking_overlay(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        if !self.opponent_thinking {
+            return;
         }
 
-        // Handle promotion overlay
-        if let UIState::Promotion { column, .. } = self.board.ui_state {
-            let choices = [PieceKind::Queen, PieceKind::Rook, PieceKind::Bishop, PieceKind::Knight];
-            let start_x = SCREEN_WIDTH / 2.0 - 2.0 * SQUARE_SIZE;
-            let y_choice = SCREEN_HEIGHT / 2.0 - SQUARE_SIZE / 2.0;
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(self.layout.board_rect())
+                .color(self.theme.faint_dim),
+        );
+        draw_centered_text(
+            canvas,
+            ctx,
+            "Opponent is thinking...",
+            0.3 * self.layout.square_size,
+            self.layout.board_center(),
+            self.theme.faint_text,
+        );
+    }
 
-            for (i, kind) in choices.iter().enumerate() {
-                let x_choice = start_x + i as f32 * SQUARE_SIZE;
-                let inside = x >= x_choice && x <= x_choice + SQUARE_SIZE
-                    && y >= y_choice && y <= y_choice + SQUARE_SIZE;
+    // Dim the board while waiting for the opponent to connect
+    fn draw_waiting_overlay(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let Some(msg) = &self.waiting_message else { return };
 
-                if inside {
-                    self.board.perform_move(HalfMoveRequest::Promotion { column, kind: *kind });
-                    self.board.ui_state = UIState::Normal;
-                    self.board.selected_position = None;
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(self.layout.window_rect())
+                .color(self.theme.dim),
+        );
+        draw_centered_text(
+            canvas,
+            ctx,
+            msg,
+            0.4 * self.layout.square_size,
+            self.layout.board_center(),
+            self.theme.overlay_text,
+        );
 
-                    unimplemented!("Promotion not implemented");
-                }
+        // A ring of dots under the message, the brightest one going round, so it
+        // is clear the game hasn't frozen while it waits
+        let square_size = self.layout.square_size;
+        let [x, y] = self.layout.board_center();
+        let center = [x, y + 0.8 * square_size];
+        let lead = (ctx.time.time_since_start().as_millis() / SPINNER_STEP.as_millis()) as usize % SPINNER_DOTS;
+        for i in 0..SPINNER_DOTS {
+            let angle = std::f32::consts::TAU * i as f32 / SPINNER_DOTS as f32;
+            let dot = [center[0] + 0.3 * square_size * angle.sin(), center[1] - 0.3 * square_size * angle.cos()];
+            // Dots further behind the lead fade out
+            let behind = (lead + SPINNER_DOTS - i) % SPINNER_DOTS;
+            let mut color = self.theme.overlay_text;
+            color.a *= 1.0 - behind as f32 / SPINNER_DOTS as f32;
+            if let Ok(mesh) =
+                graphics::Mesh::new_circle(ctx, graphics::DrawMode::fill(), dot, 0.05 * square_size, 0.5, color)
+            {
+                canvas.draw(&mesh, graphics::DrawParam::new());
             }
-            return Ok(());
         }
+    }
 
-        // Normal board interaction
-        let col = (x / SQUARE_SIZE).floor() as u8;
-        let rank = 7 - (y / SQUARE_SIZE).floor() as u8;
-        let clicked_position = Position::new(col, rank).unwrap();
-        let clicked_square = self.board.game.board().at_position(clicked_position);
-
-        match self.board.selected_position {
-            Some(src_position) => {
-                if let Some(valid_moves) = self.board.game.valid_moves(src_position) {
-                    if valid_moves.into_iter().any(|mv| mv == clicked_position) {
-                        // Pawn promotion
-                        if let Slot::Occupied(piece) = self.board.game.board().at_position(src_position) {
-                            let is_promotion_rank =
-                                (piece.color == Color::White && clicked_position.row() == 7) ||
-                                (piece.color == Color::Black && clicked_position.row() == 0);
-
-                            if piece.kind == PieceKind::Pawn && is_promotion_rank {
-                                self.board.ui_state = UIState::Promotion {
-                                    column: clicked_position.column,
-                                    color: piece.color,
-                                };
-                                self.board.selected_position = None;
-                                return Ok(());
-                            }
-                        }
-                        // Regular move
-                        self.board.perform_move(HalfMoveRequest::Standard {
-                            source: src_position,
-                            dest: clicked_position,
-                        });
-                        let message = Message::Move(MessageMove {
-                            board: self.board.game.board().clone(),
-                            mv: (src_position, clicked_position),
-                            prom_piece: None,
-                            game_state: protocol::GameState::Ongoing,
-                        });
-
-                        match self.stream.as_mut() {
-                            Some(stream) => { let _ = send_message(&stream, &message); }
-                            None => { self.playing_as = if self.playing_as == Color::White {Color::Black} else {Color::White}; }
-                        };
+    // Draw the network error message until it is clicked away
+    fn draw_error_overlay(&self, canvas: &mut graphics::Canvas, ctx: &Context) {
+        let Some(msg) = &self.error_message else { return };
 
-                    }
+        let dim_rect = self.layout.window_rect();
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(dim_rect)
+                .color(self.theme.dim),
+        );
+
+        let square_size = self.layout.square_size;
+        let board = self.layout.board_rect();
+        let panel = graphics::Rect::new(
+            board.x + square_size,
+            board.y + board.h / 2.0 - 1.5 * square_size,
+            board.w - 2.0 * square_size,
+            3.0 * square_size,
+        );
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(panel)
+                .color(self.theme.error_panel),
+        );
+
+        let mut text = graphics::Text::new(graphics::TextFragment {
+            text: msg.clone(),
+            scale: Some(graphics::PxScale::from(0.32 * square_size)),
+            ..Default::default()
+        });
+        text.set_bounds([panel.w - square_size / 2.0, panel.h]);
+        let dims = text.dimensions(ctx);
+        let dest = [
+            panel.x + panel.w / 2.0 - dims.w / 2.0,
+            panel.y + panel.h / 2.0 - dims.h / 2.0 - square_size / 4.0,
+        ];
+        canvas.draw(&text, graphics::DrawParam::new().dest(dest).color(self.theme.overlay_text));
+
+        draw_centered_text(
+            canvas,
+            ctx,
+            "Click to dismiss",
+            0.2 * square_size,
+            [panel.x + panel.w / 2.0, panel.y + panel.h - square_size / 3.0],
+            self.theme.faint_text,
+        );
+    }
+
+    // Show `msg` in the status bar
+    fn set_status(&mut self, msg: String) {
+        info!("{}", msg);
+        self.status = msg;
+    }
+
+    // Show a network error in the overlay and the status bar
+    fn show_error(&mut self, msg: String) {
+        self.set_status(msg.clone());
+        self.error_message = Some(msg);
+    }
+
+    // Apply a move to the game, queue its sound and report it in the status bar.
+    // An illegal move leaves the game as it was and returns why.
+    fn apply(&mut self, mv: (Position, Position), prom: Option<PieceKind>) -> Result<Applied, String> {
+        let color = self.state.turn();
+        let applied = match self.state.apply(mv, prom) {
+            Ok(applied) => applied,
+            Err(why) => {
+                self.set_status(format!("Illegal move: {}", why));
+                return Err(why);
+            }
+        };
+        self.applied(color, &applied);
+        Ok(applied)
+    }
+
+    // Clock, status and sound for a move `color` just made
+    fn applied(&mut self, color: Color, applied: &Applied) {
+        let mover = if color == Color::White { "White" } else { "Black" };
+        if let Some(clock) = self.clock.as_mut() {
+            clock.add_increment(color);
+        }
+
+        // A new move brings the board back to the live position
+        self.viewing = None;
+
+        let mut msg = format!("{} played {}", mover, applied.notation);
+        if applied.declined_draw {
+            msg += ", declining the draw offer";
+        }
+        if let Some(outcome) = applied.outcome {
+            msg += &format!(". {}", outcome.description());
+            info!("{}", self.state.history());
+        }
+        self.set_status(msg);
+        self.queue_sound(Self::move_sound(applied.capture, applied.check));
+    }
+
+    // Undo the last move, dropping the selection and animation tied to it
+    fn take_back(&mut self) -> bool {
+        if !self.state.undo() {
+            return false;
+        }
+        self.selected_position = None;
+        self.viewing = None;
+        self.animation = None;
+        self.annotations.clear();
+        self.ui_state = UIState::Normal;
+        self.set_status("Took back the last move".to_string());
+        true
+    }
+
+    // Step through the move history by `step` plies without touching the game.
+    // Stepping past the last move returns to the live position.
+    fn view_history(&mut self, step: isize) {
+        let halfmoves = self.state.halfmoves();
+        let current = self.viewing.unwrap_or(halfmoves);
+        let ply = current.saturating_add_signed(step).min(halfmoves);
+        if ply == current {
+            return;
+        }
+        self.selected_position = None;
+        if ply == halfmoves {
+            self.viewing = None;
+            self.set_status("Back to the current position".to_string());
+        } else {
+            self.viewing = Some(ply);
+            self.set_status(format!("Viewing after halfmove {ply} of {halfmoves}, Right returns to the game"));
+        }
+    }
+
+    // Replace the game with a loaded one, clearing anything tied to the old position
+    fn restore(&mut self, state: ChessState) {
+        self.state = state;
+        self.selected_position = None;
+        self.viewing = None;
+        self.animation = None;
+        self.annotations.clear();
+        self.ui_state = UIState::Normal;
+    }
+
+    // Perform a move made on this board, animating it unless it promotes.
+    // Like `apply`, a rejected move changes nothing and returns why, so callers
+    // only tell the opponent about moves that went through.
+    fn perform_move(&mut self, mv: (Position, Position), prom: Option<PieceKind>) -> Result<Applied, String> {
+        let applied = self.apply(mv, prom)?;
+        if prom.is_none() {
+            self.start_animation(mv.0, mv.1, applied.move_type);
+        }
+        Ok(applied)
+    }
+}
+
+// Timer for `--demo`, which plays random moves for both sides
+struct Demo {
+    interval: Duration,
+    elapsed: Duration,
+}
+
+// Keepalive timers for a network game started with `--ping-timeout`
+struct Keepalive {
+    timeout: Duration,
+    since_ping: Duration,
+    since_heard: Duration,
+}
+
+impl Keepalive {
+    fn new(timeout: Duration) -> Self {
+        Self { timeout, since_ping: Duration::ZERO, since_heard: Duration::ZERO }
+    }
+
+    // A few pings fit in the timeout, so one lost to a slow tick doesn't end the game
+    fn interval(&self) -> Duration {
+        self.timeout / 3
+    }
+}
+
+// A network connection being set up on another thread, so the window can open
+// while the server waits for its opponent
+struct Connecting {
+    receiver: Receiver<io::Result<TcpStream>>,
+    // Shown until the connection is made, e.g. "Waiting for opponent..."
+    message: String,
+}
+
+impl Connecting {
+    // Run `connect` on a new thread, reporting its stream through the receiver
+    fn spawn(message: String, connect: impl FnOnce() -> io::Result<TcpStream> + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(connect());
+        });
+        Self { receiver, message }
+    }
+}
+
+// How to get a dropped network game's connection back: the host accepts on its
+// listener again, the client connects to the same address again
+enum Reconnect {
+    Accept(TcpListener),
+    Connect(String),
+}
+
+impl Reconnect {
+    fn spawn(&self) -> io::Result<Connecting> {
+        Ok(match self {
+            Reconnect::Accept(listener) => {
+                let listener = listener.try_clone()?;
+                let message = "Connection lost, waiting for the opponent to reconnect...".to_string();
+                Connecting::spawn(message, move || listener.accept().map(|(stream, _addr)| stream))
+            }
+            Reconnect::Connect(address) => {
+                let address = address.clone();
+                let message = format!("Connection lost, reconnecting to {address}...");
+                Connecting::spawn(message, move || connect_with_retries(&address, RECONNECT_ATTEMPTS, RECONNECT_DELAY))
+            }
+        })
+    }
+}
+
+// Main game container
+struct MyGame {
+    board: GUIBoard,
+    stream: Option<TcpStream>,
+    // Set until the network opponent has connected
+    connecting: Option<Connecting>,
+    playing_as: Color,
+    // Set once the network game has ended with an error; no more reads or moves
+    disconnected: bool,
+    outgoing: SendBuffer,
+    incoming: ReadBuffer,
+    // Set while waiting for the opponent to answer our takeback request
+    takeback_pending: bool,
+    // Set while waiting for the opponent to answer our rematch request
+    rematch_pending: bool,
+    // `--flip`, to turn the board the right way again when a rematch swaps colors
+    flip: bool,
+    // `--rotate`: hot-seat game where the board turns to the side to move
+    rotate: Option<Rotate>,
+    // `--auto-queen`: promote to a queen without showing the overlay
+    auto_queen: bool,
+    // Local game against the computer, which plays the side opposite `playing_as`
+    ai: bool,
+    // Drives the computer's choices, so a seeded game plays out the same way again
+    rng: Rng,
+    // Set by `--demo`: no human input, random moves for both sides
+    demo: Option<Demo>,
+    // Set by `--debug`: F12 prints the position and legal moves as JSON
+    debug: bool,
+    // Opened on the first copy and kept, since on some systems the copied text
+    // only lasts as long as the clipboard handle
+    clipboard: Option<arboard::Clipboard>,
+    keepalive: Option<Keepalive>,
+    // Kept by a `--keep-hosting` server to accept the next opponent after each game
+    host: Option<TcpListener>,
+    // Set in network games, to get the connection back if it drops mid-game
+    reconnect: Option<Reconnect>,
+    // Set from reconnecting until the opponent's position has been checked
+    // against ours; no moves until then
+    resyncing: bool,
+    // Set once the finished game's result line is on stdout
+    result_printed: bool,
+    // Frames in a row the opponent sent that couldn't be parsed
+    corrupt_frames: u32,
+}
+
+impl MyGame {
+    pub fn new(ctx: &mut Context, connecting: Option<Connecting>, playing_as: Color, options: &Options) -> Self {
+        let mut board = GUIBoard::new(ctx, options);
+        let network_color = connecting.as_ref().map(|_| playing_as);
+        board.layout = board.layout.with_flipped(black_at_bottom(options.flip, network_color));
+        match &connecting {
+            Some(connecting) => {
+                board.waiting_message = Some(connecting.message.clone());
+                board.toolbar = Some(Toolbar::default());
+                board.set_status(connecting.message.clone());
+            }
+            // Keep a piece loading warning visible until the first move
+            None if !board.status.is_empty() => {}
+            None if options.ai => board.status = "Playing White against the computer".to_string(),
+            None if options.demo.is_some() => board.status = "Demo: random moves for both sides".to_string(),
+            None => board.status = "Local game, White to move".to_string(),
+        }
+
+        let keepalive = options.ping_timeout.filter(|_| connecting.is_some()).map(Keepalive::new);
+        let rng = match options.seed {
+            Some(seed) => Rng::new(seed),
+            None => {
+                let (rng, seed) = Rng::from_entropy();
+                if options.ai || options.demo.is_some() {
+                    info!("Computer seed: {seed} (replay this game with --seed {seed})");
+                }
+                rng
+            }
+        };
+        Self {
+            board,
+            stream: None,
+            connecting,
+            playing_as,
+            disconnected: false,
+            outgoing: SendBuffer::default(),
+            incoming: ReadBuffer::default(),
+            takeback_pending: false,
+            rematch_pending: false,
+            flip: options.flip,
+            rotate: options.rotate,
+            auto_queen: options.auto_queen,
+            ai: options.ai,
+            rng,
+            demo: options.demo.map(|interval| Demo { interval, elapsed: Duration::ZERO }),
+            debug: options.debug,
+            clipboard: None,
+            keepalive,
+            host: None,
+            reconnect: None,
+            resyncing: false,
+            result_printed: false,
+            corrupt_frames: 0,
+        }
+    }
+
+    // Pick up the connection once the other thread has made it
+    fn poll_connection(&mut self) {
+        let Some(connecting) = &self.connecting else { return };
+        let result = match connecting.receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(io::Error::other("connection thread stopped")),
+        };
+
+        self.connecting = None;
+        self.board.waiting_message = None;
+        match result.and_then(|stream| stream.set_nonblocking(true).map(|_| stream)) {
+            Ok(stream) => {
+                // Who we ended up talking to, so the host can tell it's the right friend
+                let peer = stream.peer_addr().map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+                info!("Connected to {peer}");
+                self.stream = Some(stream);
+                if self.resyncing {
+                    // Both sides send their position and check the other's in `handle_message`
+                    self.send_resync();
+                    self.board.set_status(format!("Reconnected to {peer}, checking the position with the opponent..."));
+                    return;
+                }
+                let color = if self.playing_as == Color::White { "White" } else { "Black" };
+                self.board.set_status(format!("Connected to {peer}, playing as {color}"));
+            }
+            Err(e) if self.resyncing => {
+                self.board.show_error(format!("Couldn't reconnect: {e}"));
+                self.disconnected = true;
+            }
+            Err(e) => {
+                self.board.show_error(format!("Couldn't connect: {e}"));
+                self.disconnected = true;
+            }
+        }
+    }
+
+    // Send our position, for the opponent to check against theirs
+    fn send_resync(&mut self) {
+        let state = &self.board.state;
+        let resync = MessageResync {
+            board: state.board().clone(),
+            turn: state.turn(),
+            halfmoves: u16::try_from(state.halfmoves()).unwrap_or(u16::MAX),
+        };
+        self.send(&Message::Resync(resync));
+    }
+
+    // A frame from the opponent couldn't be parsed. The read buffer has already
+    // dropped it, so the connection is still usable: if it was a ping or other
+    // message that didn't change the board, comparing positions gets both sides
+    // going again. A missed move makes the positions differ, which ends the game
+    // like any desync, and so does a run of corrupt frames.
+    fn handle_corrupt_frame(&mut self, error: ParseError) {
+        self.corrupt_frames += 1;
+        if self.corrupt_frames >= MAX_CORRUPT_FRAMES {
+            self.send(&Message::Quit("Protocol violation".to_string()));
+            self.board.show_error(NetError::ParseError(error).to_string());
+            self.disconnected = true;
+            return;
+        }
+        warn!("Corrupt frame from the opponent ({error}), checking the position with them");
+        if !self.resyncing {
+            self.resyncing = true;
+            self.send_resync();
+            self.board.set_status("Received a corrupt message, checking the position with the opponent...".to_string());
+        }
+    }
+
+    // Drop the finished connection and accept a new opponent on the same listener,
+    // with `reason` for the last game ending shown in the status bar
+    fn wait_for_next_opponent(&mut self, reason: String) {
+        let Some(listener) = &self.host else { return };
+        let listener = match listener.try_clone() {
+            Ok(listener) => listener,
+            Err(e) => {
+                self.board.show_error(format!("Couldn't wait for the next opponent: {e}"));
+                self.host = None;
+                return;
+            }
+        };
+
+        info!("Waiting for next opponent...");
+        self.stream = None;
+        self.disconnected = false;
+        self.outgoing = SendBuffer::default();
+        self.incoming = ReadBuffer::default();
+        self.takeback_pending = false;
+        self.rematch_pending = false;
+        self.resyncing = false;
+        self.corrupt_frames = 0;
+        self.keepalive = self.keepalive.as_ref().map(|keepalive| Keepalive::new(keepalive.timeout));
+        // A rematch may have swapped sides; the host plays White again
+        self.playing_as = Color::White;
+        self.board.reset();
+        self.board.layout = self.board.layout.with_flipped(black_at_bottom(self.flip, Some(self.playing_as)));
+        self.board.error_message = None;
+
+        let message = "Waiting for next opponent...".to_string();
+        self.board.waiting_message = Some(message.clone());
+        self.board.set_status(format!("{reason}. {message}"));
+        self.connecting = Some(Connecting::spawn(message, move || listener.accept().map(|(stream, _addr)| stream)));
+    }
+
+    // Send `message` to the network opponent, if there is one. A failed write
+    // ends the game with an error like a failed read; returns whether the
+    // message was sent or queued.
+    fn send(&mut self, message: &Message) -> bool {
+        let Some(stream) = self.stream.as_mut() else { return false };
+        match self.outgoing.send(stream, message) {
+            Ok(_) => true,
+            Err(e @ NetError::IoError(_)) => {
+                if !self.disconnected {
+                    self.lose_connection(e.to_string());
+                }
+                false
+            }
+            // Our own message couldn't be encoded; reconnecting wouldn't help
+            Err(e) => {
+                if !self.disconnected {
+                    self.board.show_error(e.to_string());
+                    self.disconnected = true;
+                }
+                false
+            }
+        }
+    }
+
+    // The connection dropped with `error`. While the game is still on, try to
+    // get it back and resync; otherwise end the network game with the error.
+    fn lose_connection(&mut self, error: String) {
+        if self.board.state.outcome.is_none()
+            && let Some(reconnect) = &self.reconnect
+        {
+            match reconnect.spawn() {
+                Ok(connecting) => {
+                    self.stream = None;
+                    self.outgoing = SendBuffer::default();
+                    self.incoming = ReadBuffer::default();
+                    // A request the opponent may never have seen is dropped on both sides
+                    self.takeback_pending = false;
+                    if matches!(self.board.ui_state, UIState::TakebackPrompt) {
+                        self.board.ui_state = UIState::Normal;
+                    }
+                    self.resyncing = true;
+                    self.keepalive = self.keepalive.as_ref().map(|keepalive| Keepalive::new(keepalive.timeout));
+                    self.board.waiting_message = Some(connecting.message.clone());
+                    self.board.set_status(format!("{error}. {}", connecting.message));
+                    self.connecting = Some(connecting);
+                    return;
+                }
+                Err(e) => warn!("Couldn't wait for the connection to come back: {e}"),
+            }
+        }
+        self.board.show_error(error);
+        self.disconnected = true;
+    }
+
+    // Give up the network game
+    fn resign(&mut self) {
+        if self.stream.is_none() || self.disconnected || self.board.state.outcome.is_some() {
+            return;
+        }
+        let winner = if self.playing_as == Color::White { Color::Black } else { Color::White };
+        self.board.state.outcome = Some(Outcome::Resignation(winner));
+        self.board.set_status(Outcome::Resignation(winner).description().to_string());
+        self.send(&Message::Resign);
+    }
+
+    // Which toolbar buttons apply right now: both need a live game, and a draw
+    // can't be offered twice
+    fn refresh_toolbar(&mut self) {
+        let Some(toolbar) = self.board.toolbar.as_mut() else { return };
+        let live = self.stream.is_some() && !self.disconnected && self.board.state.outcome.is_none();
+        toolbar.resign = live;
+        toolbar.draw = live && self.board.state.draw_offer != Some(self.playing_as);
+    }
+
+    // Offer a draw, or accept the opponent's offer. In a local game the side to
+    // move offers; the computer never accepts, so its next move declines.
+    fn offer_draw(&mut self) {
+        if self.disconnected || self.board.state.outcome.is_some() {
+            return;
+        }
+        let by = if self.stream.is_some() || self.ai { self.playing_as } else { self.board.state.turn() };
+        if self.board.state.draw_offer == Some(by) {
+            return;
+        }
+
+        let agreed = self.board.state.offer_draw(by);
+        self.send(&Message::DrawOffer);
+        let side = if by == Color::White { "White" } else { "Black" };
+        self.board.set_status(if agreed {
+            Outcome::Draw(DrawReason::Agreement).description().to_string()
+        } else {
+            format!("{side} offers a draw: D to accept, or move to decline")
+        });
+    }
+
+    // Tell the opponent about a move we just made, or hand the board to the
+    // other side in a local game
+    fn send_move(&mut self, mv: (Position, Position), prom_piece: Option<PieceKind>) {
+        let message = Message::Move(MessageMove {
+            board: self.board.state.game.board().clone(),
+            mv,
+            prom_piece,
+            game_state: game_state(self.board.state.outcome),
+            // The spec wants the padding all '0', so the move isn't tagged
+            move_type: None,
+        });
+
+        match self.stream.as_mut() {
+            Some(_) => { self.send(&message); }
+            None if self.ai => (),
+            None => { self.playing_as = if self.playing_as == Color::White {Color::Black} else {Color::White}; }
+        };
+    }
+
+    // Finish the pending promotion with the chosen piece, picked from the overlay,
+    // with Q/R/B/N or by `--auto-queen`
+    fn promote(&mut self, kind: PieceKind) {
+        let UIState::Promotion { source, column, color } = self.board.ui_state else {
+            return;
+        };
+        self.board.ui_state = UIState::Normal;
+        self.board.selected_position = None;
+
+        let dest = Position::new(column.get(), promotion_rank(color)).unwrap();
+        if self.board.apply((source, dest), Some(kind)).is_ok() {
+            self.send_move((source, dest), Some(kind));
+        }
+    }
+
+    // Take back the last move: right away in a local game, after the opponent
+    // agrees in a network game
+    fn request_takeback(&mut self) {
+        let halfmoves = self.board.state.halfmoves();
+        if self.disconnected || self.takeback_pending || halfmoves == 0 {
+            return;
+        }
+
+        match self.stream {
+            Some(_) => {
+                let request = Message::TakebackRequest(u16::try_from(halfmoves).unwrap_or(u16::MAX));
+                if self.send(&request) {
+                    self.takeback_pending = true;
+                    self.board.set_status("Asked the opponent to take back the last move".to_string());
+                }
+            }
+            None if self.ai => {
+                // Also take back the computer's reply, so it's our turn again
+                while self.board.take_back() && self.board.state.turn() != self.playing_as {}
+            }
+            None => {
+                if self.board.take_back() {
+                    self.playing_as = self.board.state.turn();
+                }
+            }
+        }
+    }
+
+    // Reset a finished local game, or ask the network opponent for a rematch
+    fn play_again(&mut self) {
+        if self.stream.is_none() {
+            self.board.reset();
+        } else if self.send(&Message::Rematch) {
+            self.rematch_pending = true;
+            self.board.set_status("Asked the opponent for a rematch".to_string());
+        }
+    }
+
+    // With `--rotate`, turn the board so the side to move sits at the bottom. Not
+    // while a promotion is being picked, and unless rotating instantly, only once
+    // the move has slid into place.
+    fn rotate_to_mover(&mut self) {
+        let Some(rotate) = self.rotate else { return };
+        if matches!(self.board.ui_state, UIState::Promotion { .. })
+            || (rotate == Rotate::AfterAnimation && self.board.animation.is_some())
+        {
+            return;
+        }
+        let flipped = black_at_bottom(false, Some(self.board.state.turn()));
+        if self.board.layout.flipped != flipped {
+            self.board.layout = self.board.layout.with_flipped(flipped);
+        }
+    }
+
+    // Not playing again after a finished game: a `--keep-hosting` server says
+    // goodbye and waits for the next opponent, anyone else keeps the final position
+    fn dismiss_game_over(&mut self) {
+        let Some(outcome) = self.board.state.outcome else { return };
+        if self.host.is_some() && self.stream.is_some() && !self.disconnected {
+            self.send(&Message::Quit("Game over".to_string()));
+            self.wait_for_next_opponent(outcome.description().to_string());
+        }
+    }
+
+    // Start the next network game with colors swapped, once both sides agreed
+    fn start_rematch(&mut self) {
+        self.rematch_pending = false;
+        self.takeback_pending = false;
+        self.playing_as = if self.playing_as == Color::White { Color::Black } else { Color::White };
+        self.board.reset();
+        self.board.layout = self.board.layout.with_flipped(black_at_bottom(self.flip, Some(self.playing_as)));
+        let side = if self.playing_as == Color::White { "White" } else { "Black" };
+        self.board.set_status(format!("Rematch: you play {side}"));
+    }
+
+    fn save(&mut self) {
+        match fs::write(SAVE_FILE, save_game(&self.board.state)) {
+            Ok(()) => self.board.set_status(format!("Saved the game to {SAVE_FILE}")),
+            Err(err) => self.board.set_status(format!("Couldn't save the game: {err}")),
+        }
+    }
+
+    // Loading replaces the position, so it is only allowed in local games
+    fn load(&mut self) {
+        if self.stream.is_some() {
+            self.board.set_status("Loading a game is only possible in local games".to_string());
+            return;
+        }
+        let loaded = fs::read_to_string(SAVE_FILE)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| load_game(&contents));
+        match loaded {
+            Ok(state) => {
+                self.board.restore(state);
+                // Against the computer we keep our side, and it moves if it's its turn
+                if !self.ai {
+                    self.playing_as = self.board.state.turn();
+                }
+                self.board.set_status(format!("Loaded the game from {SAVE_FILE}"));
+            }
+            Err(err) => self.board.set_status(format!("Couldn't load {SAVE_FILE}: {err}")),
+        }
+    }
+
+    // A left-click, or Space / Enter on the keyboard cursor, on `position` of the
+    // live board on our turn: select a piece, or move the selected one there
+    fn click_square(&mut self, position: Position) {
+        // Only a new selection keeps a square selected
+        let click = self.board.state.click(self.board.selected_position, position);
+        self.board.selected_position = None;
+        match click {
+            Click::Select(position) => self.board.selected_position = Some(position),
+            Click::Move(src_position, dest) => {
+                // Pawn promotion
+                if let Some(color) = self.board.state.promotes(src_position, dest) {
+                    self.board.ui_state = UIState::Promotion { source: src_position, column: dest.column, color };
+                    if self.auto_queen {
+                        self.promote(PieceKind::Queen);
+                    }
+                    return;
                 }
+                // Regular move
+                if self.board.perform_move((src_position, dest), None).is_ok() {
+                    self.send_move((src_position, dest), None);
+                }
+            }
+            Click::Reject(src_position) => self.board.reject(src_position),
+            Click::Deselect => {}
+        }
+    }
+
+    // Show the keyboard cursor, or move it `dx` squares right and `dy` down the
+    // screen. It first shows up on the selected or hovered square, or else in
+    // front of the pieces at the bottom, and stops at the edge of the board.
+    fn move_key_cursor(&mut self, dx: i8, dy: i8) {
+        let board = &mut self.board;
+        board.key_cursor = Some(match board.key_cursor {
+            Some(position) => board.layout.step(position, dx, dy).unwrap_or(position),
+            None => {
+                let start = if board.layout.flipped { "e7" } else { "e2" };
+                board.selected_position.or(board.hovered_position).or_else(|| Position::parse(start)).unwrap()
+            }
+        });
+    }
+
+    // Space / Enter: what a left-click on the keyboard cursor's square would do,
+    // as long as it is our move and nothing is waiting for an answer
+    fn activate_key_cursor(&mut self) {
+        let Some(position) = self.board.key_cursor else {
+            self.move_key_cursor(0, 0);
+            return;
+        };
+        if self.board.error_message.is_some()
+            || self.disconnected
+            || self.takeback_pending
+            || self.board.state.outcome.is_some()
+            || !matches!(self.board.ui_state, UIState::Normal)
+        {
+            return;
+        }
+        if self.board.viewing.take().is_some() {
+            self.board.set_status("Back to the current position".to_string());
+            return;
+        }
+        if self.board.state.turn() == self.playing_as {
+            self.click_square(position);
+        }
+    }
+
+    // Start from a game imported with `--pgn`, showing its first position so Left
+    // and Right step through the moves
+    fn import(&mut self, state: ChessState) {
+        self.board.restore(state);
+        if !self.ai {
+            self.playing_as = self.board.state.turn();
+        }
+        let halfmoves = self.board.state.halfmoves();
+        if halfmoves > 0 {
+            self.board.viewing = Some(0);
+        }
+        self.board.set_status(format!("Imported {halfmoves} halfmoves, Left / Right step through them"));
+    }
+
+    // Run down the clock of the side to move. In a network game each side only
+    // flags its own clock and tells the opponent, so both agree on who lost.
+    fn run_clock(&mut self, dt: Duration) {
+        let turn = self.board.state.turn();
+        let Some(clock) = self.board.clock.as_mut() else { return };
+        if self.board.state.outcome.is_some() || !clock.tick(turn, dt) {
+            return;
+        }
+
+        let winner = if turn == Color::White { Color::Black } else { Color::White };
+        match self.stream {
+            Some(_) if turn == self.playing_as => {
+                self.send(&Message::Flag);
+            }
+            // Wait for the opponent's flag rather than racing it
+            Some(_) => return,
+            None => {}
+        }
+        self.board.state.outcome = Some(Outcome::Timeout(winner));
+        self.board.set_status(Outcome::Timeout(winner).description().to_string());
+    }
+
+    // Copy the position as FEN to the system clipboard, e.g. for an analysis engine
+    fn copy_fen(&mut self) {
+        let fen = self.board.state.fen();
+        let clipboard = match self.clipboard.take().map_or_else(arboard::Clipboard::new, Ok) {
+            Ok(clipboard) => self.clipboard.insert(clipboard),
+            Err(e) => {
+                self.board.set_status(format!("Couldn't open the clipboard: {e}"));
+                return;
+            }
+        };
+        match clipboard.set_text(fen.as_str()) {
+            Ok(()) => self.board.set_status(format!("Copied FEN {fen}")),
+            Err(e) => self.board.set_status(format!("Couldn't copy to the clipboard: {e}")),
+        }
+    }
+
+    // Act on one message from the network opponent
+    fn handle_message(&mut self, message: Message) {
+        // Any message shows the opponent is still there
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            keepalive.since_heard = Duration::ZERO;
+        }
+        match message {
+            Message::Move(message) => {
+                let opponent = if self.playing_as == Color::White { Color::Black } else { Color::White };
+                let applied = match self.board.state.apply_remote(opponent, &message) {
+                    Ok(applied) => applied,
+                    Err(RemoteMoveError::ProtocolViolation(why)) => {
+                        self.send(&Message::Quit("Protocol violation".to_string()));
+                        self.board.show_error(format!("Protocol violation: {why}"));
+                        self.disconnected = true;
+                        return;
+                    }
+                    Err(RemoteMoveError::Desync(why)) => {
+                        self.send(&Message::Quit("Desync".to_string()));
+                        self.board.show_error(format!("Board desync: {why}"));
+                        self.disconnected = true;
+                        return;
+                    }
+                };
+                self.board.applied(opponent, &applied);
                 self.board.selected_position = None;
+                self.board.start_animation(message.mv.0, message.mv.1, applied.move_type);
+            },
+            Message::TakebackRequest(halfmoves) => {
+                // Only agree to take back the move both sides see as the last one
+                let in_sync = usize::from(halfmoves) == self.board.state.halfmoves();
+                if in_sync && halfmoves > 0 && !self.takeback_pending {
+                    self.board.ui_state = UIState::TakebackPrompt;
+                    self.board.set_status("The opponent asks to take back the last move".to_string());
+                } else {
+                    self.send(&Message::TakebackResponse(false));
+                }
+            }
+            Message::TakebackResponse(accepted) => {
+                if self.takeback_pending {
+                    self.takeback_pending = false;
+                    if accepted {
+                        self.board.take_back();
+                    } else {
+                        self.board.set_status("The opponent declined the takeback".to_string());
+                    }
+                }
+            }
+            Message::Rematch => {
+                if self.board.state.outcome.is_none() {
+                    self.send(&Message::RematchResponse(false));
+                } else if self.rematch_pending {
+                    // Both asked at once, which is as good as both agreeing
+                    self.send(&Message::RematchResponse(true));
+                    self.start_rematch();
+                } else {
+                    self.board.ui_state = UIState::RematchPrompt;
+                    self.board.set_status("The opponent asks for a rematch".to_string());
+                }
+            }
+            Message::RematchResponse(accepted) => {
+                if self.rematch_pending {
+                    self.rematch_pending = false;
+                    if accepted {
+                        self.start_rematch();
+                    } else {
+                        self.board.set_status("The opponent declined the rematch".to_string());
+                    }
+                }
+            }
+            Message::Ping => {
+                self.send(&Message::Pong);
             }
+            Message::Pong => (),
+            Message::Flag => {
+                // Both clocks can run out at nearly the same time; the first flag decides
+                if self.board.state.outcome.is_none() {
+                    let outcome = Outcome::Timeout(self.playing_as);
+                    self.board.state.outcome = Some(outcome);
+                    self.board.set_status(outcome.description().to_string());
+                }
+            }
+            Message::DrawOffer => {
+                let opponent = if self.playing_as == Color::White { Color::Black } else { Color::White };
+                if self.board.state.offer_draw(opponent) {
+                    self.board.set_status(Outcome::Draw(DrawReason::Agreement).description().to_string());
+                } else if self.board.state.outcome.is_none() {
+                    self.board.set_status("The opponent offers a draw: D to accept, or move to decline".to_string());
+                }
+            }
+            Message::Resign => {
+                if self.board.state.outcome.is_none() {
+                    let outcome = Outcome::Resignation(self.playing_as);
+                    self.board.state.outcome = Some(outcome);
+                    self.board.set_status(outcome.description().to_string());
+                }
+            }
+            Message::Resync(theirs) => {
+                let state = &self.board.state;
+                let agreed = theirs.turn == state.turn()
+                    && usize::from(theirs.halfmoves) == state.halfmoves()
+                    && board_diff(&theirs.board, state.board()).is_empty();
+                if agreed && self.resyncing {
+                    self.resyncing = false;
+                    self.board.set_status("Positions match, the game continues".to_string());
+                } else if agreed {
+                    // The opponent asked, e.g. after a corrupt frame; answer with ours
+                    self.send_resync();
+                } else {
+                    self.send(&Message::Quit("Desync".to_string()));
+                    self.board.show_error(
+                        "Couldn't resume the game: the opponent's position differs from ours".to_string(),
+                    );
+                    log_board_diff(&theirs.board, self.board.state.board());
+                    self.disconnected = true;
+                }
+            }
+            Message::Quit(s) => {
+                self.board.show_error(format!("Opponent quit: {s}"));
+                self.disconnected = true;
+            }
+        }
+    }
+
+    // Let the computer move once the last move has finished animating
+    fn play_ai_move(&mut self) {
+        let state = &self.board.state;
+        if state.turn() == self.playing_as || state.outcome.is_some() || self.board.animation.is_some() {
+            return;
+        }
+        if let Some((mv, prom)) = choose_move(state, self.rng.next_u64()) {
+            // An illegal choice is reported in the status bar like any other
+            let _ = self.board.perform_move(mv, prom);
+        }
+    }
+
+    // Play the next random move of a `--demo` game, or start a new game a while
+    // after the last one ended
+    fn play_demo_move(&mut self, dt: Duration) {
+        let Some(demo) = self.demo.as_mut() else { return };
+        if self.board.animation.is_some() {
+            return;
+        }
+        demo.elapsed += dt;
+        let finished = self.board.state.outcome.is_some();
+        let wait = if finished { demo.interval.max(DEMO_GAME_OVER_PAUSE) } else { demo.interval };
+        if demo.elapsed < wait {
+            return;
+        }
+        demo.elapsed = Duration::ZERO;
+
+        let mv = if finished { None } else { random_move(&self.board.state, self.rng.next_u64()) };
+        match mv {
+            Some((mv, prom)) => {
+                let _ = self.board.perform_move(mv, prom);
+            }
+            // Also covers a side left without moves that the rules didn't end the game for
             None => {
-                if let Slot::Occupied(piece) = clicked_square {
-                    if piece.color == self.board.game.turn {
-                        self.board.selected_position = Some(clicked_position);
+                self.board.reset();
+                self.board.set_status("Demo: new game".to_string());
+            }
+        }
+    }
+}
+
+impl EventHandler for MyGame {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        self.board.advance_animation(ctx.time.delta());
+        self.board.play_queued_sound(ctx);
+        self.rotate_to_mover();
+
+        // Print the result line once per finished game, for scripts watching stdout
+        match self.board.state.outcome {
+            Some(outcome) if !self.result_printed => {
+                println!("{}", result_line(outcome, &self.board.state.fen()));
+                self.result_printed = true;
+            }
+            None => self.result_printed = false,
+            _ => {}
+        }
+
+        // Finish writing frames the socket wasn't ready for last tick
+        if let Some(stream) = self.stream.as_mut()
+            && !self.outgoing.is_empty()
+            && let Err(e) = self.outgoing.flush(stream)
+            && !self.disconnected
+        {
+            self.lose_connection(e.to_string());
+        }
+
+        // A `--keep-hosting` server starts over once the opponent leaves or the
+        // connection ends. A finished game stays up until the host dismisses it.
+        if self.host.is_some() && self.stream.is_some() && self.disconnected {
+            let reason = self.board.error_message.take().unwrap_or_else(|| "Connection lost".to_string());
+            self.wait_for_next_opponent(reason);
+        }
+
+        self.poll_connection();
+        // Read on both turns, since takeback messages can arrive at any time
+        if self.disconnected || self.connecting.is_some() {
+            return Ok(());
+        }
+        if self.board.state.outcome.is_none() {
+            self.board.time_spent.tick(self.board.state.turn(), ctx.time.delta());
+        }
+        self.run_clock(ctx.time.delta());
+
+        if self.stream.is_some()
+            && let Some(keepalive) = self.keepalive.as_mut()
+        {
+            keepalive.since_ping += ctx.time.delta();
+            keepalive.since_heard += ctx.time.delta();
+            if keepalive.since_heard >= keepalive.timeout {
+                let timeout = keepalive.timeout.as_secs();
+                self.lose_connection(format!("Connection lost: no answer from the opponent in {timeout} seconds"));
+                return Ok(());
+            }
+            if keepalive.since_ping >= keepalive.interval() {
+                keepalive.since_ping = Duration::ZERO;
+                if !self.send(&Message::Ping) {
+                    return Ok(());
+                }
+            }
+        }
+        if self.stream.is_none() {
+            if self.ai {
+                self.play_ai_move();
+            } else if self.demo.is_some() {
+                self.play_demo_move(ctx.time.delta());
+            }
+            return Ok(());
+        }
+        // Handle everything that has arrived, so a Quit or Flag isn't held up behind
+        // a move, until the socket would block or the connection has ended
+        while !self.disconnected
+            && let Some(stream) = self.stream.as_mut()
+        {
+            match self.incoming.next(stream) {
+                Ok(message) => {
+                    self.corrupt_frames = 0;
+                    self.handle_message(message);
+                }
+                // Keep reading: the frames after a corrupt one are still whole
+                Err(NetError::ParseError(e)) => self.handle_corrupt_frame(e),
+                Err(NetError::IoError(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e @ NetError::IoError(_)) => {
+                    self.lose_connection(e.to_string());
+                    break;
+                }
+                Err(e) => {
+                    self.board.show_error(e.to_string());
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        self.refresh_toolbar();
+        self.board.opponent_thinking = self.stream.is_some()
+            && !self.disconnected
+            && self.board.state.outcome.is_none()
+            && self.board.state.turn() != self.playing_as;
+        if self.board.show_pins {
+            self.board.state.pinned();
+        }
+        let mut canvas = graphics::Canvas::from_frame(ctx, self.board.theme.background);
+        canvas.set_sampler(match self.board.filter {
+            Filter::Nearest => graphics::Sampler::nearest_clamp(),
+            Filter::Linear => graphics::Sampler::linear_clamp(),
+        });
+        self.board.draw(&mut canvas, ctx);
+        canvas.finish(ctx)
+    }
+
+    fn mouse_button_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    ) -> GameResult {
+        // Dragging with the middle button moves the board, whatever else is going on
+        if button == MouseButton::Middle {
+            self.board.panning = true;
+            return Ok(());
+        }
+        if self.board.show_help {
+            self.board.show_help = button != MouseButton::Left;
+            return Ok(());
+        }
+        if self.demo.is_some() {
+            return Ok(());
+        }
+        if button == MouseButton::Right {
+            self.board.annotation_start = self.board.layout.position_at(x, y);
+            return Ok(());
+        }
+        if button != MouseButton::Left {
+            return Ok(());
+        }
+        self.board.annotations.clear();
+        self.board.key_cursor = None;
+        // Dismiss a network error; the board stays frozen on the last position
+        if self.board.error_message.take().is_some() || self.disconnected || self.connecting.is_some() || self.resyncing {
+            return Ok(());
+        }
+        // Resign and Offer Draw; a greyed out button just swallows the click
+        if let Some(toolbar) = self.board.toolbar {
+            let (resign, draw) = self.board.layout.toolbar_buttons();
+            if resign.contains([x, y]) {
+                if toolbar.resign {
+                    self.resign();
+                }
+                return Ok(());
+            }
+            if draw.contains([x, y]) {
+                if toolbar.draw {
+                    self.offer_draw();
+                }
+                return Ok(());
+            }
+        }
+        // Answer the opponent's takeback request
+        if let UIState::TakebackPrompt = self.board.ui_state {
+            let (yes, no) = self.board.layout.confirm_reset_buttons();
+            let accepted = if yes.contains([x, y]) {
+                true
+            } else if no.contains([x, y]) {
+                false
+            } else {
+                return Ok(());
+            };
+
+            self.board.ui_state = UIState::Normal;
+            self.send(&Message::TakebackResponse(accepted));
+            if accepted {
+                self.board.take_back();
+            } else {
+                self.board.set_status("Declined the takeback".to_string());
+            }
+            return Ok(());
+        }
+        // Answer the opponent's rematch request
+        if let UIState::RematchPrompt = self.board.ui_state {
+            let (yes, no) = self.board.layout.confirm_reset_buttons();
+            let accepted = if yes.contains([x, y]) {
+                true
+            } else if no.contains([x, y]) {
+                false
+            } else {
+                return Ok(());
+            };
+
+            self.board.ui_state = UIState::Normal;
+            self.send(&Message::RematchResponse(accepted));
+            if accepted {
+                self.start_rematch();
+            } else {
+                self.board.set_status("Declined the rematch".to_string());
+            }
+            return Ok(());
+        }
+        // A click while looking at an earlier position goes back to the game
+        if self.board.viewing.take().is_some() {
+            self.board.set_status("Back to the current position".to_string());
+            return Ok(());
+        }
+        // No moves until the opponent has answered our takeback request
+        if self.takeback_pending {
+            return Ok(());
+        }
+        // Ask before resetting a finished game, so a stray click keeps the final position.
+        // Either player can ask, so this comes before the turn check.
+        if self.board.state.outcome.is_some() {
+            match self.board.ui_state {
+                UIState::ConfirmReset => {
+                    let (yes, no) = self.board.layout.confirm_reset_buttons();
+                    if yes.contains([x, y]) {
+                        self.board.ui_state = UIState::Normal;
+                        self.play_again();
+                    } else if no.contains([x, y]) {
+                        self.board.ui_state = UIState::Normal;
+                        self.dismiss_game_over();
                     }
                 }
+                _ if self.board.layout.save_pgn_button().contains([x, y]) => self.board.save_pgn(),
+                // Nothing more to ask while our rematch request is open
+                _ if self.rematch_pending => {}
+                _ => self.board.ui_state = UIState::ConfirmReset,
+            }
+            return Ok(());
+        }
+        // Don't play if it's not your turn
+        if self.board.state.game.turn != self.playing_as {
+            return Ok(());
+        }
+
+        // Handle promotion overlay
+        if let UIState::Promotion { column, color, .. } = self.board.ui_state {
+            let tiles = self.board.layout.promotion_tiles(column, color);
+            for (kind, tile) in PROMOTION_CHOICES.into_iter().zip(tiles) {
+                if tile.contains([x, y]) {
+                    self.promote(kind);
+                    break;
+                }
             }
+            return Ok(());
         }
 
+        // Normal board interaction
+        // Clicks off the board, e.g. in the letterbox or at the window edge, are ignored
+        if let Some(clicked_position) = self.board.layout.position_at(x, y) {
+            self.click_square(clicked_position);
+        }
         Ok(())
     }
-}
 
-fn parse_cmd(mut ctx: &mut Context, args: Vec<String>) -> MyGame {
-    if let Some(address) = args.get(1) {
-        if let Some(server_str) = args.get(2) && server_str == "server" {
-            let listener = TcpListener::bind(address);
-            let listener = match listener {
-                Ok(listener) => listener,
-                Err(e) => panic!("Couldn't not bind to address '{}': {e:?}", address),
+    fn key_down_event(&mut self, _ctx: &mut Context, input: KeyInput, repeated: bool) -> GameResult {
+        if repeated {
+            return Ok(());
+        }
+        let PhysicalKey::Code(key) = input.event.physical_key else { return Ok(()) };
+        let Some(action) = action_for(key) else { return Ok(()) };
+        // The help opens at any time; the other keys wait until it is closed again
+        let busy = self.connecting.is_some() || self.resyncing || self.demo.is_some() || self.board.show_help;
+        if busy && action != Action::ToggleHelp {
+            return Ok(());
+        }
+        match action {
+            Action::Takeback => self.request_takeback(),
+            Action::Save => self.save(),
+            Action::Load => self.load(),
+            Action::OfferDraw => self.offer_draw(),
+            Action::CopyFen => self.copy_fen(),
+            // Left and Right move the keyboard cursor while it is shown
+            Action::HistoryBack if self.board.key_cursor.is_some() => self.move_key_cursor(-1, 0),
+            Action::HistoryForward if self.board.key_cursor.is_some() => self.move_key_cursor(1, 0),
+            Action::HistoryBack => self.board.view_history(-1),
+            Action::HistoryForward => self.board.view_history(1),
+            Action::CursorUp => self.move_key_cursor(0, -1),
+            Action::CursorDown => self.move_key_cursor(0, 1),
+            Action::Activate => self.activate_key_cursor(),
+            Action::HideCursor => self.board.key_cursor = None,
+            Action::Promote(kind) => self.promote(kind),
+            Action::DumpPosition if self.debug => {
+                println!("{}", self.board.state.debug_json());
+                self.board.set_status("Position dumped to the terminal".to_string());
+            }
+            Action::DumpPosition => {}
+            Action::TogglePins => {
+                self.board.show_pins = !self.board.show_pins;
+                let state = if self.board.show_pins { "on" } else { "off" };
+                self.board.set_status(format!("Pin markers {state}"));
+            }
+            Action::ToggleHighlights => {
+                self.board.show_highlights = !self.board.show_highlights;
+                let state = if self.board.show_highlights { "on" } else { "off" };
+                self.board.set_status(format!("Move highlights {state}"));
+            }
+            Action::ResetZoom => self.board.layout = self.board.layout.reset_zoom(),
+            Action::ToggleHelp => self.board.show_help = !self.board.show_help,
+        }
+        Ok(())
+    }
+
+    fn resize_event(&mut self, _ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        self.board.layout = self.board.layout.resized(width, height);
+        Ok(())
+    }
+
+    // Tell the opponent we left, so they don't wait for us to reconnect
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        if self.stream.is_some() && !self.disconnected {
+            self.reconnect = None;
+            self.send(&Message::Quit("Window closed".to_string()));
+        }
+        Ok(false)
+    }
+
+    fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, dx: f32, dy: f32) -> GameResult {
+        if self.board.panning {
+            self.board.layout = self.board.layout.panned([dx, dy]);
+        }
+        self.board.cursor = [x, y];
+        self.board.hovered_position = self.board.layout.position_at(x, y);
+        Ok(())
+    }
+
+    // Zoom the board around the mouse, a tenth per notch
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) -> GameResult {
+        if y != 0.0 {
+            self.board.layout = self.board.layout.zoom_at(1.1_f32.powf(y), self.board.cursor);
+            let [x, y] = self.board.cursor;
+            self.board.hovered_position = self.board.layout.position_at(x, y);
+        }
+        Ok(())
+    }
+
+    fn mouse_button_up_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    ) -> GameResult {
+        if button == MouseButton::Middle {
+            self.board.panning = false;
+        }
+        // A right-click on one square highlights it, a right-drag draws an arrow
+        if button == MouseButton::Right
+            && let Some(start) = self.board.annotation_start.take()
+            && let Some(end) = self.board.layout.position_at(x, y)
+        {
+            let annotation = if start == end {
+                Annotation::Square(start)
+            } else {
+                Annotation::Arrow(start, end)
             };
-            println!("Waiting for opponent...");
-            let stream = match listener.accept() {
-                Ok((stream, _addr)) => { let _ = stream.set_nonblocking(true); MyGame::new(&mut ctx, Some(stream), Color::White) },
-                Err(e) => panic!("Opponent failed to connect: {e:?}"),
+            self.board.toggle_annotation(annotation);
+        }
+        Ok(())
+    }
+}
+
+fn start_game(ctx: &mut Context, mode: &Mode, options: &Options) -> MyGame {
+    match mode {
+        Mode::Local => MyGame::new(ctx, None, Color::White, options),
+        Mode::Host(address) => {
+            let (listener, mut local_addr) = match listen(address) {
+                Ok(bound) => bound,
+                Err(e) => {
+                    eprintln!("{}", bind_error_message(address, &e));
+                    std::process::exit(1);
+                }
             };
-            print!("Opponent connected!");
-            stream
-        } else if let Some(client_str) = args.get(2) && client_str == "client" { 
-            let stream = match TcpStream::connect(address) {
-                Ok(stream) => stream,
-                Err(e) => panic!("Failed to connect to opponent: {e:?}"),
+            info!("Listening on {local_addr}");
+            // 0.0.0.0 isn't somewhere the opponent can connect to, so show this machine's address
+            if local_addr.ip().is_unspecified()
+                && let Some(ip) = lan_address()
+            {
+                local_addr.set_ip(ip);
+                info!("Opponents on your network can connect to {local_addr}");
+            }
+            let host = if options.keep_hosting {
+                match listener.try_clone() {
+                    Ok(listener) => Some(listener),
+                    Err(e) => {
+                        eprintln!("Couldn't keep listening for more opponents: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                None
             };
-            let _ = stream.set_nonblocking(true);
-            MyGame::new(&mut ctx, Some(stream), Color::Black)
-        } else {
-            panic!("You have to specify 'server' or 'client' after the address");
+            let reconnect = listener.try_clone().ok().map(Reconnect::Accept);
+            let message = format!("Waiting for opponent on {local_addr}...");
+            let connecting = Connecting::spawn(message, move || listener.accept().map(|(stream, _addr)| stream));
+            let mut game = MyGame::new(ctx, Some(connecting), Color::White, options);
+            game.host = host;
+            game.reconnect = reconnect;
+            game
+        }
+        Mode::Join(address) => {
+            let address = address.clone();
+            let reconnect = Reconnect::Connect(address.clone());
+            let connecting = Connecting::spawn(format!("Connecting to {address}..."), move || TcpStream::connect(address));
+            let mut game = MyGame::new(ctx, Some(connecting), Color::Black, options);
+            game.reconnect = Some(reconnect);
+            game
         }
-    } else {
-        MyGame::new(&mut ctx, None, Color::White)
     }
 }
 
+// Print `msg` and the usage, then exit with an error
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{msg}\n\n{USAGE}");
+    std::process::exit(2);
+}
+
 fn main() {
+    // Diagnostics go to stderr, so stdout only carries what was asked for, like --replay's FEN.
+    // RUST_LOG picks the level, showing info and above by default.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = env::args().collect();
+    let (options, args) = parse_options(args).unwrap_or_else(|e| usage_error(&e));
+    if options.help {
+        println!("{USAGE}");
+        return;
+    }
+    let mode = parse_mode(&args, options.listen).unwrap_or_else(|e| usage_error(&e));
+
+    if options.ai && mode != Mode::Local {
+        usage_error("--ai is only available in local games");
+    }
+    if options.demo.is_some() && (options.ai || mode != Mode::Local) {
+        usage_error("--demo plays both sides itself, so it can't be combined with --ai or a network game");
+    }
+    if options.rotate.is_some() && (options.ai || options.demo.is_some() || mode != Mode::Local) {
+        usage_error("--rotate is only available in local games between two players");
+    }
+    if options.keep_hosting && !matches!(mode, Mode::Host(_)) {
+        usage_error("--keep-hosting is only available when hosting");
+    }
+    if options.pgn.is_some() && (options.demo.is_some() || mode != Mode::Local) {
+        usage_error("--pgn is only available in local games, and not with --demo");
+    }
+
+    // Headless mode: print where the moves lead and exit without opening a window
+    if let Some(path) = &options.replay {
+        let result = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read '{}': {e}", path.display()))
+            .and_then(|input| replay(&input));
+        match result {
+            Ok(result) => {
+                println!("{}", result.fen());
+                if let Some(outcome) = result.outcome {
+                    println!("{}", result_line(outcome, &result.fen()));
+                }
+                return;
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Read the imported game before opening the window, so a bad file is reported right away
+    let imported = options.pgn.as_ref().map(|path| {
+        fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read '{}': {e}", path.display()))
+            .and_then(|input| from_pgn(&input).map_err(|e| format!("{}: {e}", path.display())))
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            })
+    });
+
+    let resources = resource_dir(&options, env::var(RESOURCES_ENV).ok());
+    if let Err(e) = check_resources(&resources) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    let (width, height) = options.window_size.unwrap_or(DEFAULT_WINDOW_SIZE);
+    let mut window_mode = ggez::conf::WindowMode::default()
+        .dimensions(width, height)
+        .resizable(true);
+    if options.fullscreen {
+        window_mode = window_mode.fullscreen_type(ggez::conf::FullscreenType::Desktop);
+    }
+
     let (mut ctx, event_loop) = ContextBuilder::new("my_game", "Author")
-        .window_mode(ggez::conf::WindowMode::default().dimensions(SCREEN_WIDTH, SCREEN_HEIGHT))
-        .add_resource_path("./resources")
+        .window_mode(window_mode)
+        .add_resource_path(resources)
         .build()
         .expect("Failed to create ggez context");
 
     ctx.gfx.set_window_title("Chess");
 
-    let args: Vec<String> = env::args().collect();
-    let my_game = parse_cmd(&mut ctx, args);
+    let mut my_game = start_game(&mut ctx, &mode, &options);
+    if let Some(state) = imported {
+        my_game.import(state);
+    }
 
     event::run(ctx, event_loop, my_game).expect("Program failed");
 }
@@ -1,6 +1,7 @@
-use std::{io::{Read, Write}, net::{TcpStream}};
+use std::{io::{self, ErrorKind, Read, Write}, net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket}};
+use std::{fmt, thread, time::Duration};
 
-use crate::protocol::{parse, serialize, Message, SerializeError, ParseError};
+use crate::protocol::{parse, serialize, Message, SerializeError, ParseError, FRAME_LEN};
 
 #[derive(Debug)]
 pub enum NetError {
@@ -9,6 +10,27 @@ pub enum NetError {
     IoError(std::io::Error),
 }
 
+// What went wrong, for showing to the player
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::ParseError(e) => write!(f, "Received malformed data from the opponent: {e}"),
+            NetError::SerializeError(e) => write!(f, "Failed to encode your move: {e}"),
+            NetError::IoError(e) => write!(f, "Connection lost: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for NetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NetError::ParseError(e) => Some(e),
+            NetError::SerializeError(e) => Some(e),
+            NetError::IoError(e) => Some(e),
+        }
+    }
+}
+
 impl From<ParseError> for NetError {
     fn from(e: ParseError) -> Self {
         NetError::ParseError(e)
@@ -27,22 +49,402 @@ impl From<std::io::Error> for NetError {
     }
 }
 
+// Bind the server socket. Port 0 lets the OS pick a free port, so the address
+// actually bound is returned to be shared with the opponent.
+pub fn listen(address: &str) -> io::Result<(TcpListener, SocketAddr)> {
+    let listener = TcpListener::bind(address)?;
+    let local_addr = listener.local_addr()?;
+    Ok((listener, local_addr))
+}
+
+// Connect to `address`, trying up to `attempts` times with `delay` between
+// tries, e.g. to get back to a host after the connection dropped. Returns the
+// last error if every attempt fails.
+pub fn connect_with_retries(address: &str, attempts: u32, delay: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::other("no connection attempts");
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            thread::sleep(delay);
+        }
+        match TcpStream::connect(address) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+// Readable explanation of why `listen(address)` failed
+pub fn bind_error_message(address: &str, e: &io::Error) -> String {
+    let why = match e.kind() {
+        ErrorKind::AddrInUse => "the port is already in use, maybe by another game".to_string(),
+        ErrorKind::AddrNotAvailable => "that IP isn't one of this machine's; use 0.0.0.0 to listen on all of them".to_string(),
+        ErrorKind::PermissionDenied => "not allowed to use that port; try one above 1024".to_string(),
+        ErrorKind::InvalidInput => "expected IP:PORT, e.g. 0.0.0.0:5000".to_string(),
+        _ => e.to_string(),
+    };
+    format!("Couldn't host on '{address}': {why}")
+}
+
+// This machine's address on the local network, for telling the opponent where
+// to connect when listening on all interfaces. Connecting a UDP socket only
+// picks the outgoing interface; nothing is sent.
+pub fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified() && !ip.is_loopback()).then_some(ip)
+}
+
 pub fn read_message(stream: &mut TcpStream) -> Result<Message, NetError> {
-    let mut message = [0; 128];
+    let mut message = [0; FRAME_LEN];
     let buf = stream.read_exact(&mut message);
     match buf {
-        Ok(_) => {
-            let str = String::from_utf8_lossy(&message).to_string();
-            let message = parse(&str)?;
-            Ok(message)
-        },
+        Ok(_) => parse_frame(&message),
         Err(e) => Err(NetError::IoError(e))
     }
 }
 
-pub fn send_message(mut stream: &TcpStream, message: &Message) -> Result<(), NetError> {
+fn parse_frame(frame: &[u8]) -> Result<Message, NetError> {
+    // The protocol is plain ASCII, so any other byte means a corrupt frame
+    let str = std::str::from_utf8(frame)
+        .ok()
+        .filter(|str| str.is_ascii())
+        .ok_or(ParseError::NonAscii)?;
+    Ok(parse(str)?)
+}
+
+// Write a whole frame to a blocking stream. Non-blocking streams should use a
+// `SendBuffer` instead, so a partial write isn't lost.
+pub fn send_message(stream: &mut TcpStream, message: &Message) -> Result<(), NetError> {
     let message = serialize(message)?;
     stream.write_all(message.as_bytes())?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SendStatus {
+    // Everything queued so far has been written
+    Sent,
+    // The socket would block; the rest goes out on a later `flush`
+    Queued,
+}
+
+// Outgoing bytes for a non-blocking socket, so a frame interrupted by
+// `WouldBlock` is completed later instead of losing the framing
+#[derive(Default)]
+pub struct SendBuffer {
+    pending: Vec<u8>,
+}
+
+impl SendBuffer {
+    pub fn send<W: Write>(&mut self, stream: &mut W, message: &Message) -> Result<SendStatus, NetError> {
+        let message = serialize(message)?;
+        self.pending.extend_from_slice(message.as_bytes());
+        self.flush(stream)
+    }
+
+    pub fn flush<W: Write>(&mut self, stream: &mut W) -> Result<SendStatus, NetError> {
+        while !self.pending.is_empty() {
+            match stream.write(&self.pending) {
+                Ok(0) => return Err(NetError::IoError(io::Error::from(ErrorKind::WriteZero))),
+                Ok(written) => {
+                    self.pending.drain(..written);
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(SendStatus::Queued),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(NetError::IoError(e)),
+            }
+        }
+
+        Ok(SendStatus::Sent)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+// Incoming bytes for a non-blocking socket, so a frame that arrives in pieces
+// is put back together instead of losing the framing
+#[derive(Default)]
+pub struct ReadBuffer {
+    received: Vec<u8>,
+}
+
+impl ReadBuffer {
+    // The next complete message, reading whatever the socket has. Returns a
+    // `WouldBlock` error once no whole frame is left, keeping any partial one.
+    pub fn next<R: Read>(&mut self, stream: &mut R) -> Result<Message, NetError> {
+        let mut chunk = [0; 4 * FRAME_LEN];
+        while self.received.len() < FRAME_LEN {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(NetError::IoError(io::Error::from(ErrorKind::UnexpectedEof))),
+                Ok(read) => self.received.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(NetError::IoError(e)),
+            }
+        }
+
+        let frame: Vec<u8> = self.received.drain(..FRAME_LEN).collect();
+        parse_frame(&frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsoderh_chess::{Board, Color, Position};
+
+    use super::*;
+    use crate::protocol::{parse_fen, GameState, MessageMove, MessageResync, MoveType};
+
+    #[test]
+    fn bind_errors_are_readable() {
+        let (_listener, addr) = listen("127.0.0.1:0").expect("bind localhost");
+        let address = addr.to_string();
+        let e = listen(&address).expect_err("port is taken");
+        let msg = bind_error_message(&address, &e);
+        assert!(msg.contains(&address), "{msg}");
+        assert!(msg.contains("already in use"), "{msg}");
+
+        let e = listen("localhost").expect_err("no port");
+        assert!(bind_error_message("localhost", &e).starts_with("Couldn't host on 'localhost'"));
+    }
+
+    // A connected (client, server) pair of blocking sockets on localhost
+    fn connected_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind localhost");
+        let addr = listener.local_addr().expect("listener address");
+        let client = TcpStream::connect(addr).expect("connect to listener");
+        let (server, _addr) = listener.accept().expect("accept client");
+        (client, server)
+    }
+
+    #[test]
+    fn move_and_quit_round_trip_over_localhost() {
+        let (mut client, mut server) = connected_pair();
+
+        let mv = Message::Move(MessageMove {
+            board: Board::new_empty(),
+            mv: (Position::new(4, 1).unwrap(), Position::new(4, 3).unwrap()),
+            prom_piece: None,
+            game_state: GameState::Ongoing,
+            move_type: Some(MoveType::Normal),
+        });
+        let quit = Message::Quit("Good game".to_string());
+
+        send_message(&mut client, &mv).expect("send move");
+        send_message(&mut client, &quit).expect("send quit");
+
+        assert_eq!(read_message(&mut server).expect("read move"), mv);
+        assert_eq!(read_message(&mut server).expect("read quit"), quit);
+    }
+
+    #[test]
+    fn ping_and_pong_over_localhost() {
+        let (mut client, mut server) = connected_pair();
+
+        send_message(&mut client, &Message::Ping).expect("send ping");
+        assert_eq!(read_message(&mut server).expect("read ping"), Message::Ping);
+        send_message(&mut server, &Message::Pong).expect("send pong");
+        assert_eq!(read_message(&mut client).expect("read pong"), Message::Pong);
+    }
+
+    #[test]
+    fn reconnect_and_resync_over_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind localhost");
+        let address = listener.local_addr().expect("listener address").to_string();
+        let mut client = connect_with_retries(&address, 3, Duration::from_millis(10)).expect("connect");
+        let (server, _addr) = listener.accept().expect("accept client");
+
+        // The connection drops mid-game
+        drop(server);
+        assert!(matches!(read_message(&mut client), Err(NetError::IoError(_))));
+
+        // The client comes back to the same address and both sides send their position
+        let mut client = connect_with_retries(&address, 3, Duration::from_millis(10)).expect("reconnect");
+        let (mut server, _addr) = listener.accept().expect("accept the reconnection");
+        let position = || {
+            let board = parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").expect("valid FEN");
+            Message::Resync(MessageResync { board, turn: Color::Black, halfmoves: 1 })
+        };
+        send_message(&mut client, &position()).expect("client resync");
+        send_message(&mut server, &position()).expect("server resync");
+        assert_eq!(read_message(&mut server).expect("read client resync"), position());
+        assert_eq!(read_message(&mut client).expect("read server resync"), position());
+    }
+
+    #[test]
+    fn connect_with_retries_gives_up() {
+        // A port that was free a moment ago, with nothing listening on it any more
+        let address = TcpListener::bind("127.0.0.1:0").and_then(|l| l.local_addr()).expect("free port").to_string();
+        let e = connect_with_retries(&address, 2, Duration::from_millis(10)).expect_err("nothing listening");
+        assert_eq!(e.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn write_errors_are_io_errors() {
+        let (mut client, _server) = connected_pair();
+        client.shutdown(std::net::Shutdown::Write).expect("shut down writes");
+        let err = send_message(&mut client, &Message::Ping).expect_err("writing after shutdown");
+        assert!(matches!(err, NetError::IoError(_)), "{err:?}");
+    }
+
+    // Accepts at most `chunk` bytes per write and blocks on every other call
+    struct ShortWriter {
+        written: Vec<u8>,
+        chunk: usize,
+        block_next: bool,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.block_next = !self.block_next;
+            if !self.block_next {
+                return Err(io::Error::from(ErrorKind::WouldBlock));
+            }
+            let n = buf.len().min(self.chunk);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_buffer_completes_short_writes_in_order() {
+        let mut writer = ShortWriter { written: Vec::new(), chunk: 50, block_next: false };
+        let mut buffer = SendBuffer::default();
+
+        let first = Message::Quit("first".to_string());
+        let second = Message::Quit("second".to_string());
+        assert_eq!(buffer.send(&mut writer, &first).expect("queue first"), SendStatus::Queued);
+        assert_eq!(buffer.send(&mut writer, &second).expect("queue second"), SendStatus::Queued);
+
+        let mut flushes = 0;
+        while buffer.flush(&mut writer).expect("flush") == SendStatus::Queued {
+            flushes += 1;
+            assert!(flushes < 10, "buffer never drained");
+        }
+        assert!(buffer.is_empty());
+
+        let expected = serialize(&first).unwrap() + &serialize(&second).unwrap();
+        assert_eq!(writer.written.len(), 256);
+        assert_eq!(writer.written, expected.as_bytes());
+    }
+
+    #[test]
+    fn read_after_peer_closed_is_io_error() {
+        let (client, mut server) = connected_pair();
+        drop(client);
+
+        let err = read_message(&mut server).expect_err("peer closed the connection");
+        assert!(matches!(err, NetError::IoError(_)));
+    }
+
+    #[test]
+    fn read_rejects_non_ascii_bytes() {
+        let (mut client, mut server) = connected_pair();
+        let mut frame = serialize(&Message::Quit("cafe".to_string())).unwrap().into_bytes();
+        // Turn "cafe" into "café" in Latin-1
+        frame[13] = 0xe9;
+        client.write_all(&frame).expect("send frame");
+
+        let err = read_message(&mut server).expect_err("non-ASCII frame");
+        assert!(matches!(err, NetError::ParseError(ParseError::NonAscii)));
+    }
+
+    #[test]
+    fn listen_on_port_zero_picks_a_free_port() {
+        let (_listener, addr) = listen("127.0.0.1:0").expect("bind localhost");
+        assert_ne!(addr.port(), 0);
+        assert!(addr.ip().is_loopback());
+
+        TcpStream::connect(addr).expect("connect to the resolved address");
+    }
+
+    // Hands out at most `chunk` bytes per read and blocks on every other call
+    struct ShortReader {
+        data: Vec<u8>,
+        chunk: usize,
+        block_next: bool,
+    }
+
+    impl Read for ShortReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.block_next = !self.block_next;
+            if !self.block_next || self.data.is_empty() {
+                return Err(io::Error::from(ErrorKind::WouldBlock));
+            }
+            let n = buf.len().min(self.chunk).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Ok(n)
+        }
+    }
+
+    fn would_block(result: Result<Message, NetError>) -> bool {
+        matches!(result, Err(NetError::IoError(e)) if e.kind() == ErrorKind::WouldBlock)
+    }
+
+    #[test]
+    fn read_buffer_reassembles_split_frames_in_order() {
+        let frames = [Message::Ping, Message::Quit("Good game".to_string()), Message::Flag];
+        let data = frames.iter().map(|message| serialize(message).unwrap()).collect::<String>();
+        let mut reader = ShortReader { data: data.into_bytes(), chunk: 50, block_next: false };
+        let mut buffer = ReadBuffer::default();
+
+        let mut received = Vec::new();
+        for _ in 0..50 {
+            match buffer.next(&mut reader) {
+                Ok(message) => received.push(message),
+                result => assert!(would_block(result), "only WouldBlock while a frame is incomplete"),
+            }
+        }
+        assert_eq!(received, frames);
+    }
+
+    #[test]
+    fn read_buffer_drains_frames_that_arrived_together() {
+        let (mut client, mut server) = connected_pair();
+        send_message(&mut client, &Message::Ping).expect("send ping");
+        send_message(&mut client, &Message::Resign).expect("send resign");
+        drop(client);
+
+        let mut buffer = ReadBuffer::default();
+        assert_eq!(buffer.next(&mut server).expect("read ping"), Message::Ping);
+        assert_eq!(buffer.next(&mut server).expect("read resign"), Message::Resign);
+        let err = buffer.next(&mut server).expect_err("peer closed the connection");
+        assert!(matches!(err, NetError::IoError(e) if e.kind() == ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn read_buffer_skips_a_corrupt_frame() {
+        let good = serialize(&Message::Ping).unwrap();
+        // A MOVE frame whose board field is cut short and holds a stray character
+        let corrupt = format!("{:0<128}", "ChessMOVE:E2E40:0-0:rnbqkbnr/pppp?ppp/8:0");
+        let data = format!("{corrupt}{good}");
+        let mut reader = data.as_bytes();
+        let mut buffer = ReadBuffer::default();
+
+        let err = buffer.next(&mut reader).expect_err("corrupt board");
+        assert!(matches!(err, NetError::ParseError(_)), "{err:?}");
+        assert_eq!(buffer.next(&mut reader).expect("the next frame is whole"), Message::Ping);
+    }
+
+    #[test]
+    fn errors_read_as_sentences() {
+        let parse = NetError::from(ParseError::InvalidFENRank { rank: 3 });
+        assert_eq!(
+            parse.to_string(),
+            "Received malformed data from the opponent: rank 3 of the board doesn't have eight files"
+        );
+        let serialize = NetError::from(SerializeError::ColonInQuitMsg);
+        assert_eq!(serialize.to_string(), "Failed to encode your move: the quit message contains a ':'");
+        let io = NetError::from(io::Error::from(ErrorKind::ConnectionReset));
+        assert!(io.to_string().starts_with("Connection lost: "), "{io}");
+    }
+}
@@ -0,0 +1,122 @@
+use rsoderh_chess::{Board, CheckOutcome, PieceKind, Position, Slot};
+
+use puhl_gui::protocol::{classify_move, MoveType};
+use crate::state::square_name;
+
+// Check suffix of a move in notation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Check {
+    None,
+    Check,
+    Checkmate,
+}
+
+impl From<&CheckOutcome> for Check {
+    fn from(outcome: &CheckOutcome) -> Self {
+        match outcome {
+            CheckOutcome::Check => Check::Check,
+            _ => Check::None,
+        }
+    }
+}
+
+// Letter of a piece in notation; pawns have none
+pub fn piece_letter(kind: PieceKind) -> &'static str {
+    match kind {
+        PieceKind::Pawn => "",
+        PieceKind::Knight => "N",
+        PieceKind::Bishop => "B",
+        PieceKind::Rook => "R",
+        PieceKind::Queen => "Q",
+        PieceKind::King => "K",
+    }
+}
+
+// Long algebraic notation of `mv` played on `before`, e.g. `Ng1-f3`, `e4xd5`,
+// `e7-e8=Q+` or `O-O`
+pub fn describe_move(before: &Board, mv: (Position, Position), prom: Option<PieceKind>, check: Check) -> String {
+    let (src, dst) = mv;
+    let mut notation = match classify_move(before, mv) {
+        MoveType::CastleKingside => "O-O".to_string(),
+        MoveType::CastleQueenside => "O-O-O".to_string(),
+        move_type => {
+            let piece = match before.at_position(src) {
+                Slot::Occupied(piece) => piece_letter(piece.kind),
+                Slot::Empty => "",
+            };
+            let capture = move_type == MoveType::EnPassant || matches!(before.at_position(dst), Slot::Occupied(_));
+            let separator = if capture { 'x' } else { '-' };
+            format!("{}{}{}{}", piece, square_name(src), separator, square_name(dst))
+        }
+    };
+
+    if let Some(kind) = prom {
+        notation += "=";
+        notation += piece_letter(kind);
+    }
+    match check {
+        Check::None => {}
+        Check::Check => notation.push('+'),
+        Check::Checkmate => notation.push('#'),
+    }
+    notation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsoderh_chess::{Color, Game, Piece};
+
+    fn pos(s: &str) -> Position {
+        Position::parse(s).unwrap()
+    }
+
+    fn place(board: &mut Board, square: &str, color: Color, kind: PieceKind) {
+        *board.at_position_mut(pos(square)) = Slot::Occupied(Piece { color, kind });
+    }
+
+    #[test]
+    fn quiet_moves_from_the_start() {
+        let board = Game::new_standard().board().clone();
+        assert_eq!(describe_move(&board, (pos("e2"), pos("e4")), None, Check::None), "e2-e4");
+        assert_eq!(describe_move(&board, (pos("g1"), pos("f3")), None, Check::None), "Ng1-f3");
+    }
+
+    #[test]
+    fn captures_and_en_passant() {
+        let mut board = Board::new_empty();
+        place(&mut board, "e4", Color::White, PieceKind::Pawn);
+        place(&mut board, "d5", Color::Black, PieceKind::Pawn);
+        place(&mut board, "e5", Color::White, PieceKind::Pawn);
+        place(&mut board, "c3", Color::White, PieceKind::Bishop);
+        place(&mut board, "g7", Color::Black, PieceKind::Rook);
+
+        assert_eq!(describe_move(&board, (pos("e4"), pos("d5")), None, Check::None), "e4xd5");
+        assert_eq!(describe_move(&board, (pos("c3"), pos("g7")), None, Check::None), "Bc3xg7");
+        // d6 is empty, so this is an en passant capture
+        assert_eq!(describe_move(&board, (pos("e5"), pos("d6")), None, Check::None), "e5xd6");
+    }
+
+    #[test]
+    fn promotion_and_check_markers() {
+        let mut board = Board::new_empty();
+        place(&mut board, "e7", Color::White, PieceKind::Pawn);
+        place(&mut board, "f8", Color::Black, PieceKind::Knight);
+        place(&mut board, "h1", Color::White, PieceKind::Queen);
+
+        assert_eq!(describe_move(&board, (pos("e7"), pos("e8")), Some(PieceKind::Queen), Check::Check), "e7-e8=Q+");
+        assert_eq!(describe_move(&board, (pos("e7"), pos("f8")), Some(PieceKind::Knight), Check::None), "e7xf8=N");
+        assert_eq!(describe_move(&board, (pos("h1"), pos("h7")), None, Check::Checkmate), "Qh1-h7#");
+    }
+
+    #[test]
+    fn castling() {
+        let mut board = Board::new_empty();
+        place(&mut board, "e1", Color::White, PieceKind::King);
+        place(&mut board, "h1", Color::White, PieceKind::Rook);
+        place(&mut board, "a1", Color::White, PieceKind::Rook);
+
+        assert_eq!(describe_move(&board, (pos("e1"), pos("g1")), None, Check::None), "O-O");
+        assert_eq!(describe_move(&board, (pos("e1"), pos("c1")), None, Check::Check), "O-O-O+");
+    }
+}
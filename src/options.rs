@@ -0,0 +1,672 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::clock::TimeControl;
+
+// How piece images are sampled when scaled to the square size
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Filter {
+    // Crisp pixels, for pixel-art piece sets
+    Nearest,
+    // Smooth scaling, for high resolution sets
+    #[default]
+    Linear,
+}
+
+// When `--rotate` turns the board to the side to move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotate {
+    // Once the moved piece has slid into place
+    AfterAnimation,
+    // As soon as the move is made
+    Instant,
+}
+
+// Colors of the window around the board; see `Theme`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ThemeKind {
+    #[default]
+    Light,
+    Dark,
+}
+
+// What the positional arguments ask for: a game on this machine, or hosting
+// or joining a network game at an address
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Mode {
+    #[default]
+    Local,
+    Host(String),
+    Join(String),
+}
+
+// Printed with `--help` and after invalid usage
+pub const USAGE: &str = "\
+Usage: puhl-gui [options] [local]
+       puhl-gui [options] host <address>
+       puhl-gui [options] join <address>
+
+  local             play both sides on this machine (the default)
+  host <address>    wait for an opponent on <address>, e.g. 0.0.0.0:5000, and play White
+  join <address>    connect to a host at <address> and play Black
+
+'<address> server' and '<address> client' are accepted for host and join.
+See the README for the options.";
+
+// Command line flags, independent of the positional network arguments
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub help: bool,
+    pub mute: bool,
+    pub pieces: Option<PathBuf>,
+    pub light: Option<[u8; 3]>,
+    pub dark: Option<[u8; 3]>,
+    // Highlight of the selected square and of its legal moves, RGBA
+    pub select_color: Option<[u8; 4]>,
+    pub move_color: Option<[u8; 4]>,
+    pub window_size: Option<(f32, f32)>,
+    pub fullscreen: bool,
+    pub replay: Option<PathBuf>,
+    // Game to start from, to step through its moves
+    pub pgn: Option<PathBuf>,
+    pub flip: bool,
+    // Turn the board to the side to move after each move, for hot-seat games
+    pub rotate: Option<Rotate>,
+    pub no_highlights: bool,
+    // Mark pieces pinned to their king
+    pub pins: bool,
+    // Name the square under the mouse, e.g. `e4`, for learning the coordinates
+    pub square_names: bool,
+    // Lines between the squares and around the board
+    pub grid: bool,
+    // Promote straight to a queen instead of asking with the overlay
+    pub auto_queen: bool,
+    pub ai: bool,
+    // Annotate the exported PGN with the time spent on each move
+    pub pgn_times: bool,
+    // Enable developer keys, e.g. dumping the position as JSON
+    pub debug: bool,
+    // Seed for the computer opponent's choices; random if unset
+    pub seed: Option<u64>,
+    // Server only: wait for a new opponent after each game instead of stopping
+    pub keep_hosting: bool,
+    // Host on all interfaces on this port, like `host 0.0.0.0:PORT`
+    pub listen: Option<u16>,
+    // Directory holding `pieces/` and `sounds/`; see `resource_dir`
+    pub resources: Option<PathBuf>,
+    // Declare a network opponent gone after this long without hearing from them
+    pub ping_timeout: Option<Duration>,
+    // Time each side starts with and gets per move; no clocks if unset
+    pub clock: Option<TimeControl>,
+    // Play random moves for both sides, one per interval
+    pub demo: Option<Duration>,
+    pub filter: Filter,
+    pub theme: ThemeKind,
+}
+
+// Environment variable naming the resource directory when `--resources` isn't given
+pub const RESOURCES_ENV: &str = "PUHL_GUI_RESOURCES";
+const DEFAULT_RESOURCES: &str = "./resources";
+
+// Bounds for `--window-size`, in pixels per dimension
+const MIN_WINDOW_SIZE: u32 = 200;
+const MAX_WINDOW_SIZE: u32 = 8192;
+
+// Split the recognised `--flags` out of `args`, returning the options and the
+// remaining positional arguments (program name included)
+pub fn parse_options(args: Vec<String>) -> Result<(Options, Vec<String>), String> {
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => options.help = true,
+            "--mute" => options.mute = true,
+            "--pieces" => options.pieces = Some(PathBuf::from(flag_value(&arg, args.next())?)),
+            "--light" => options.light = Some(parse_hex_color(&arg, &flag_value(&arg, args.next())?)?),
+            "--dark" => options.dark = Some(parse_hex_color(&arg, &flag_value(&arg, args.next())?)?),
+            "--select-color" => options.select_color = Some(parse_hex_rgba(&arg, &flag_value(&arg, args.next())?)?),
+            "--move-color" => options.move_color = Some(parse_hex_rgba(&arg, &flag_value(&arg, args.next())?)?),
+            "--window-size" => options.window_size = Some(parse_window_size(&arg, &flag_value(&arg, args.next())?)?),
+            "--fullscreen" => options.fullscreen = true,
+            "--flip" => options.flip = true,
+            "--rotate" => options.rotate = options.rotate.or(Some(Rotate::AfterAnimation)),
+            "--rotate-instant" => options.rotate = Some(Rotate::Instant),
+            "--no-highlights" => options.no_highlights = true,
+            "--pins" => options.pins = true,
+            "--square-names" => options.square_names = true,
+            "--grid" => options.grid = true,
+            "--auto-queen" => options.auto_queen = true,
+            "--ai" => options.ai = true,
+            "--pgn-times" => options.pgn_times = true,
+            "--debug" => options.debug = true,
+            "--keep-hosting" => options.keep_hosting = true,
+            "--listen" => options.listen = Some(parse_port(&arg, &flag_value(&arg, args.next())?)?),
+            "--seed" => options.seed = Some(parse_seed(&arg, &flag_value(&arg, args.next())?)?),
+            "--filter" => options.filter = parse_filter(&arg, &flag_value(&arg, args.next())?)?,
+            "--theme" => options.theme = parse_theme(&arg, &flag_value(&arg, args.next())?)?,
+            "--clock" => {
+                let base = parse_minutes(&arg, &flag_value(&arg, args.next())?)?;
+                options.clock = Some(TimeControl { base, increment: Duration::ZERO });
+            }
+            "--time" => options.clock = Some(parse_time_control(&arg, &flag_value(&arg, args.next())?)?),
+            "--demo" => options.demo = Some(parse_millis(&arg, &flag_value(&arg, args.next())?)?),
+            "--ping-timeout" => options.ping_timeout = Some(parse_timeout(&arg, &flag_value(&arg, args.next())?)?),
+            "--resources" => options.resources = Some(PathBuf::from(flag_value(&arg, args.next())?)),
+            "--replay" => options.replay = Some(PathBuf::from(flag_value(&arg, args.next())?)),
+            "--pgn" => options.pgn = Some(PathBuf::from(flag_value(&arg, args.next())?)),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{flag}'")),
+            _ => positional.push(arg),
+        }
+    }
+
+    Ok((options, positional))
+}
+
+// Turn the positional arguments left by `parse_options` (program name
+// included) into a `Mode`. `--listen PORT` is short for `host 0.0.0.0:PORT`.
+pub fn parse_mode(positional: &[String], listen: Option<u16>) -> Result<Mode, String> {
+    let rest: Vec<&str> = positional.iter().skip(1).map(String::as_str).collect();
+    let mode = match rest.as_slice() {
+        [] | ["local"] => Mode::Local,
+        ["host", address] | [address, "server"] => Mode::Host(address.to_string()),
+        ["join", address] | [address, "client"] => Mode::Join(address.to_string()),
+        ["host"] => return Err("'host' needs the address to listen on, e.g. 'host 0.0.0.0:5000'".to_string()),
+        ["join"] => return Err("'join' needs the address of the host, e.g. 'join 192.168.1.20:5000'".to_string()),
+        [_address] => return Err("Add 'server' or 'client' after the address, or use 'host <address>' or 'join <address>'".to_string()),
+        _ => return Err(format!("Unexpected arguments '{}'", rest.join(" "))),
+    };
+
+    match (listen, mode) {
+        (None, mode) => Ok(mode),
+        (Some(port), Mode::Local) if rest.is_empty() => Ok(Mode::Host(format!("0.0.0.0:{port}"))),
+        (Some(_), _) => Err("--listen replaces 'host <address>', so it can't be combined with other arguments".to_string()),
+    }
+}
+
+// Resource directory: `--resources`, then `env` (the value of
+// $PUHL_GUI_RESOURCES), then `./resources` relative to the working directory
+pub fn resource_dir(options: &Options, env: Option<String>) -> PathBuf {
+    options
+        .resources
+        .clone()
+        .or_else(|| env.filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_RESOURCES))
+}
+
+// Check that `dir` has the `pieces/` folder the default piece images load from
+pub fn check_resources(dir: &Path) -> Result<(), String> {
+    if dir.join("pieces").is_dir() {
+        return Ok(());
+    }
+    let dir = dir.display();
+    Err(format!(
+        "Resource directory '{dir}' has no 'pieces' folder. Expected:\n  \
+         {dir}/pieces/  the piece images, white-king.png to black-pawn.png\n  \
+         {dir}/sounds/  move.wav, capture.wav and check.wav (optional)\n\
+         Pass the directory with --resources <dir> or ${RESOURCES_ENV}"
+    ))
+}
+
+fn flag_value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Missing value for '{flag}'"))
+}
+
+// Parse an `RRGGBB` color, with or without a leading '#'
+fn parse_hex_color(flag: &str, value: &str) -> Result<[u8; 3], String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid color '{value}' for '{flag}', expected RRGGBB"));
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok([channel(0), channel(2), channel(4)])
+}
+
+// Parse an `RRGGBB` or `RRGGBBAA` color, with or without a leading '#'. Without
+// an alpha the color is half transparent, like the default highlights.
+fn parse_hex_rgba(flag: &str, value: &str) -> Result<[u8; 4], String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid color '{value}' for '{flag}', expected RRGGBB or RRGGBBAA"));
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    let alpha = if hex.len() == 8 { channel(6) } else { 0x80 };
+    Ok([channel(0), channel(2), channel(4), alpha])
+}
+
+// Parse a `WxH` window size, e.g. `1024x768`
+fn parse_window_size(flag: &str, value: &str) -> Result<(f32, f32), String> {
+    let invalid = || format!("Invalid window size '{value}' for '{flag}', expected WxH");
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+
+    let range = MIN_WINDOW_SIZE..=MAX_WINDOW_SIZE;
+    if !range.contains(&width) || !range.contains(&height) {
+        return Err(format!(
+            "Window size '{value}' for '{flag}' must be between {MIN_WINDOW_SIZE} and {MAX_WINDOW_SIZE} pixels per side"
+        ));
+    }
+    Ok((width as f32, height as f32))
+}
+
+// Parse a whole number of seconds, at least one
+fn parse_timeout(flag: &str, value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(format!("Invalid timeout '{value}' for '{flag}', expected a number of seconds")),
+    }
+}
+
+// Parse a whole number of milliseconds, at least one
+fn parse_millis(flag: &str, value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+        _ => Err(format!("Invalid interval '{value}' for '{flag}', expected a number of milliseconds")),
+    }
+}
+
+fn parse_port(flag: &str, value: &str) -> Result<u16, String> {
+    value.parse().map_err(|_| format!("Invalid port '{value}' for '{flag}', expected 0 to 65535"))
+}
+
+fn parse_seed(flag: &str, value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("Invalid seed '{value}' for '{flag}', expected a whole number"))
+}
+
+fn parse_filter(flag: &str, value: &str) -> Result<Filter, String> {
+    match value {
+        "nearest" => Ok(Filter::Nearest),
+        "linear" => Ok(Filter::Linear),
+        _ => Err(format!("Invalid filter '{value}' for '{flag}', expected nearest or linear")),
+    }
+}
+
+fn parse_theme(flag: &str, value: &str) -> Result<ThemeKind, String> {
+    match value {
+        "light" => Ok(ThemeKind::Light),
+        "dark" => Ok(ThemeKind::Dark),
+        _ => Err(format!("Invalid theme '{value}' for '{flag}', expected dark or light")),
+    }
+}
+
+// Parse a whole number of minutes, at least one
+fn parse_minutes(flag: &str, value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(minutes) if minutes > 0 => Ok(Duration::from_secs(60 * minutes)),
+        _ => Err(format!("Invalid time '{value}' for '{flag}', expected a number of minutes")),
+    }
+}
+
+// Longest base time and increment `--time` accepts, in seconds
+const MAX_BASE_SECONDS: u64 = 10 * 60 * 60;
+const MAX_INCREMENT_SECONDS: u64 = 10 * 60;
+
+// Parse `<base>[+<increment>]` in whole seconds, e.g. `300` or `180+2`. The base
+// is at least one second and at most ten hours, the increment at most ten minutes.
+fn parse_time_control(flag: &str, value: &str) -> Result<TimeControl, String> {
+    let invalid = || {
+        format!(
+            "Invalid time control '{value}' for '{flag}', expected <base>[+<increment>] in seconds, \
+             e.g. 300 or 180+2, with a base of 1 to {MAX_BASE_SECONDS} and an increment of at most {MAX_INCREMENT_SECONDS}"
+        )
+    };
+    let (base, increment) = match value.split_once('+') {
+        Some((base, increment)) => (base, increment),
+        None => (value, "0"),
+    };
+    // Digits only, since `u64` parsing accepts a leading '+'
+    let seconds = |field: &str| field.parse::<u64>().ok().filter(|_| field.bytes().all(|b| b.is_ascii_digit()));
+    match (seconds(base), seconds(increment)) {
+        (Some(base), Some(increment)) if (1..=MAX_BASE_SECONDS).contains(&base) && increment <= MAX_INCREMENT_SECONDS => {
+            Ok(TimeControl { base: Duration::from_secs(base), increment: Duration::from_secs(increment) })
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_options_splits_flags_from_positionals() {
+        let (options, positional) = parse_options(args(&["chess", "--mute", "127.0.0.1:5000", "server"]))
+            .expect("valid arguments");
+        assert!(options.mute);
+        assert_eq!(positional, args(&["chess", "127.0.0.1:5000", "server"]));
+    }
+
+    #[test]
+    fn parse_options_help() {
+        for flag in ["--help", "-h"] {
+            let (options, _) = parse_options(args(&["chess", flag])).expect("valid arguments");
+            assert!(options.help);
+        }
+    }
+
+    #[test]
+    fn parse_mode_subcommands() {
+        assert_eq!(parse_mode(&args(&["chess"]), None), Ok(Mode::Local));
+        assert_eq!(parse_mode(&args(&["chess", "local"]), None), Ok(Mode::Local));
+        assert_eq!(
+            parse_mode(&args(&["chess", "host", "0.0.0.0:5000"]), None),
+            Ok(Mode::Host("0.0.0.0:5000".to_string()))
+        );
+        assert_eq!(
+            parse_mode(&args(&["chess", "join", "10.0.0.2:5000"]), None),
+            Ok(Mode::Join("10.0.0.2:5000".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_mode_accepts_server_and_client() {
+        assert_eq!(
+            parse_mode(&args(&["chess", "127.0.0.1:5000", "server"]), None),
+            Ok(Mode::Host("127.0.0.1:5000".to_string()))
+        );
+        assert_eq!(
+            parse_mode(&args(&["chess", "127.0.0.1:5000", "client"]), None),
+            Ok(Mode::Join("127.0.0.1:5000".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_mode_listen() {
+        assert_eq!(parse_mode(&args(&["chess"]), Some(5000)), Ok(Mode::Host("0.0.0.0:5000".to_string())));
+        for extra in [&["local"][..], &["host", "127.0.0.1:5000"], &["127.0.0.1:5000", "client"]] {
+            let mut positional = args(&["chess"]);
+            positional.extend(args(extra));
+            let err = parse_mode(&positional, Some(5000)).expect_err("--listen with arguments");
+            assert!(err.contains("--listen"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_mode_rejects_malformed_usage() {
+        for bad in [
+            &["host"][..],
+            &["join"],
+            &["127.0.0.1:5000"],
+            &["127.0.0.1:5000", "serve"],
+            &["local", "extra"],
+            &["host", "127.0.0.1:5000", "extra"],
+        ] {
+            let mut positional = args(&["chess"]);
+            positional.extend(args(bad));
+            assert!(parse_mode(&positional, None).is_err(), "{bad:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_options_defaults() {
+        let (options, positional) = parse_options(args(&["chess"])).expect("valid arguments");
+        assert_eq!(options, Options::default());
+        assert_eq!(positional, args(&["chess"]));
+    }
+
+    #[test]
+    fn parse_options_pieces_dir() {
+        let (options, positional) = parse_options(args(&["chess", "--pieces", "themes/wood"]))
+            .expect("valid arguments");
+        assert_eq!(options.pieces, Some(PathBuf::from("themes/wood")));
+        assert_eq!(positional, args(&["chess"]));
+    }
+
+    #[test]
+    fn parse_options_no_highlights() {
+        let (options, _) = parse_options(args(&["chess", "--no-highlights"])).expect("valid arguments");
+        assert!(options.no_highlights);
+    }
+
+    #[test]
+    fn parse_options_pins() {
+        let (options, _) = parse_options(args(&["chess", "--pins"])).expect("valid arguments");
+        assert!(options.pins);
+    }
+
+    #[test]
+    fn parse_options_auto_queen() {
+        let (options, _) = parse_options(args(&["chess"])).expect("valid arguments");
+        assert!(!options.auto_queen, "the overlay is the default");
+        let (options, _) = parse_options(args(&["chess", "--auto-queen"])).expect("valid arguments");
+        assert!(options.auto_queen);
+    }
+
+    #[test]
+    fn parse_options_grid() {
+        let (options, _) = parse_options(args(&["chess"])).expect("valid arguments");
+        assert!(!options.grid, "off by default");
+        let (options, _) = parse_options(args(&["chess", "--grid"])).expect("valid arguments");
+        assert!(options.grid);
+    }
+
+    #[test]
+    fn parse_options_square_names() {
+        let (options, _) = parse_options(args(&["chess", "--square-names"])).expect("valid arguments");
+        assert!(options.square_names);
+        assert!(!options.pins);
+    }
+
+    #[test]
+    fn parse_options_ai() {
+        let (options, positional) = parse_options(args(&["chess", "--ai"])).expect("valid arguments");
+        assert!(options.ai);
+        assert_eq!(positional, args(&["chess"]));
+    }
+
+    #[test]
+    fn parse_options_pgn() {
+        let (options, _) = parse_options(args(&["chess", "--pgn", "games/opera.pgn"])).expect("valid arguments");
+        assert_eq!(options.pgn, Some(PathBuf::from("games/opera.pgn")));
+        assert!(parse_options(args(&["chess", "--pgn"])).is_err());
+    }
+
+    #[test]
+    fn parse_options_pgn_times() {
+        let (options, _) = parse_options(args(&["chess", "--pgn-times"])).expect("valid arguments");
+        assert!(options.pgn_times);
+    }
+
+    #[test]
+    fn parse_options_debug() {
+        let (options, positional) = parse_options(args(&["chess", "--debug"])).expect("valid arguments");
+        assert!(options.debug);
+        assert_eq!(positional, args(&["chess"]));
+    }
+
+    #[test]
+    fn parse_options_keep_hosting() {
+        let (options, positional) = parse_options(args(&["chess", "127.0.0.1:5000", "server", "--keep-hosting"]))
+            .expect("valid arguments");
+        assert!(options.keep_hosting);
+        assert_eq!(positional, args(&["chess", "127.0.0.1:5000", "server"]));
+    }
+
+    #[test]
+    fn parse_options_listen() {
+        let (options, positional) = parse_options(args(&["chess", "--listen", "5000"])).expect("valid arguments");
+        assert_eq!(options.listen, Some(5000));
+        assert_eq!(positional, args(&["chess"]));
+
+        for bad in ["65536", "-1", "0.0.0.0:5000"] {
+            let err = parse_options(args(&["chess", "--listen", bad])).expect_err("invalid port");
+            assert!(err.contains("--listen"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_options_seed() {
+        let (options, _) = parse_options(args(&["chess", "--ai", "--seed", "1234"])).expect("valid arguments");
+        assert_eq!(options.seed, Some(1234));
+
+        for bad in ["-1", "0x10", "lucky"] {
+            let err = parse_options(args(&["chess", "--seed", bad])).expect_err("invalid seed");
+            assert!(err.contains("--seed"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_options_ping_timeout() {
+        let (options, _) = parse_options(args(&["chess", "--ping-timeout", "30"])).expect("valid arguments");
+        assert_eq!(options.ping_timeout, Some(Duration::from_secs(30)));
+
+        for bad in ["0", "-5", "1.5", "soon"] {
+            let err = parse_options(args(&["chess", "--ping-timeout", bad])).expect_err("invalid timeout");
+            assert!(err.contains("--ping-timeout"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_options_clock() {
+        let (options, _) = parse_options(args(&["chess", "--clock", "5"])).expect("valid arguments");
+        assert_eq!(options.clock, Some(TimeControl { base: Duration::from_secs(300), increment: Duration::ZERO }));
+
+        let err = parse_options(args(&["chess", "--clock", "0"])).expect_err("invalid time");
+        assert!(err.contains("--clock"), "error should name the flag: {err}");
+    }
+
+    #[test]
+    fn parse_options_time_control() {
+        for (value, base, increment) in [("300", 300, 0), ("300+5", 300, 5), ("180+2", 180, 2), ("60+0", 60, 0)] {
+            let (options, _) = parse_options(args(&["chess", "--time", value])).expect("valid time control");
+            let expected = TimeControl { base: Duration::from_secs(base), increment: Duration::from_secs(increment) };
+            assert_eq!(options.clock, Some(expected), "{value}");
+        }
+
+        for bad in ["-5+abc", "-5", "300+-5", "300+", "+5", "0", "0+5", "abc", "300+5+1", "300 + 5", "36001", "300+601"] {
+            let err = parse_options(args(&["chess", "--time", bad])).expect_err("invalid time control");
+            assert!(err.contains("--time"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_options_rotate() {
+        let (options, _) = parse_options(args(&["chess"])).expect("valid arguments");
+        assert_eq!(options.rotate, None);
+        let (options, _) = parse_options(args(&["chess", "--rotate"])).expect("valid arguments");
+        assert_eq!(options.rotate, Some(Rotate::AfterAnimation));
+        for order in [["--rotate", "--rotate-instant"], ["--rotate-instant", "--rotate"]] {
+            let (options, _) = parse_options(args(&["chess", order[0], order[1]])).expect("valid arguments");
+            assert_eq!(options.rotate, Some(Rotate::Instant), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn parse_options_demo() {
+        let (options, _) = parse_options(args(&["chess", "--demo", "250"])).expect("valid arguments");
+        assert_eq!(options.demo, Some(Duration::from_millis(250)));
+
+        for bad in ["0", "-1", "0.5"] {
+            let err = parse_options(args(&["chess", "--demo", bad])).expect_err("invalid interval");
+            assert!(err.contains("--demo"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_options_filter() {
+        let (options, _) = parse_options(args(&["chess"])).expect("valid arguments");
+        assert_eq!(options.filter, Filter::Linear);
+        let (options, _) = parse_options(args(&["chess", "--filter", "nearest"])).expect("valid arguments");
+        assert_eq!(options.filter, Filter::Nearest);
+
+        let err = parse_options(args(&["chess", "--filter", "bicubic"])).expect_err("invalid filter");
+        assert!(err.contains("--filter"), "error should name the flag: {err}");
+    }
+
+    #[test]
+    fn parse_options_theme() {
+        let (options, _) = parse_options(args(&["chess"])).expect("valid arguments");
+        assert_eq!(options.theme, ThemeKind::Light);
+        let (options, _) = parse_options(args(&["chess", "--theme", "dark"])).expect("valid arguments");
+        assert_eq!(options.theme, ThemeKind::Dark);
+
+        let err = parse_options(args(&["chess", "--theme", "solarized"])).expect_err("invalid theme");
+        assert!(err.contains("--theme"), "error should name the flag: {err}");
+    }
+
+    #[test]
+    fn resource_dir_precedence() {
+        let (options, _) = parse_options(args(&["chess", "--resources", "/opt/chess"])).expect("valid arguments");
+        assert_eq!(resource_dir(&options, Some("/env".to_string())), PathBuf::from("/opt/chess"));
+
+        let options = Options::default();
+        assert_eq!(resource_dir(&options, Some("/env".to_string())), PathBuf::from("/env"));
+        assert_eq!(resource_dir(&options, Some(String::new())), PathBuf::from("./resources"));
+        assert_eq!(resource_dir(&options, None), PathBuf::from("./resources"));
+    }
+
+    #[test]
+    fn check_resources_wants_a_pieces_folder() {
+        let repo = Path::new(env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(check_resources(&repo.join("resources")), Ok(()));
+
+        let err = check_resources(&repo.join("src")).expect_err("no pieces folder");
+        assert!(err.contains("pieces"), "error should name the missing folder: {err}");
+        assert!(err.contains("--resources"), "error should say how to fix it: {err}");
+    }
+
+    #[test]
+    fn parse_options_missing_value() {
+        let err = parse_options(args(&["chess", "--pieces"])).expect_err("missing value");
+        assert!(err.contains("--pieces"));
+    }
+
+    #[test]
+    fn parse_options_board_colors() {
+        let (options, _) = parse_options(args(&["chess", "--light", "f0d9b5", "--dark", "#B58863"]))
+            .expect("valid arguments");
+        assert_eq!(options.light, Some([0xf0, 0xd9, 0xb5]));
+        assert_eq!(options.dark, Some([0xb5, 0x88, 0x63]));
+    }
+
+    #[test]
+    fn parse_options_rejects_invalid_color() {
+        for bad in ["f0d9b", "f0d9b5aa", "zzzzzz", ""] {
+            let err = parse_options(args(&["chess", "--light", bad])).expect_err("invalid color");
+            assert!(err.contains("--light"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_options_highlight_colors() {
+        let (options, _) = parse_options(args(&["chess", "--select-color", "00ff00", "--move-color", "#0000FFc0"]))
+            .expect("valid arguments");
+        assert_eq!(options.select_color, Some([0x00, 0xff, 0x00, 0x80]));
+        assert_eq!(options.move_color, Some([0x00, 0x00, 0xff, 0xc0]));
+
+        for bad in ["00ff0", "00ff00f", "00ff00ff00", "gg0000"] {
+            let err = parse_options(args(&["chess", "--move-color", bad])).expect_err("invalid color");
+            assert!(err.contains("--move-color"), "error should name the flag: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_options_rejects_unknown_flag() {
+        let err = parse_options(args(&["chess", "--loud"])).expect_err("unknown flag");
+        assert!(err.contains("--loud"));
+    }
+
+    #[test]
+    fn parse_options_window_size() {
+        let (options, _) = parse_options(args(&["chess", "--window-size", "1024x768", "--fullscreen"]))
+            .expect("valid arguments");
+        assert_eq!(options.window_size, Some((1024.0, 768.0)));
+        assert!(options.fullscreen);
+    }
+
+    #[test]
+    fn parse_options_rejects_invalid_window_size() {
+        for bad in ["1024", "x768", "1024x", "-800x600", "0x0", "100x100", "10000x800", "axb"] {
+            let err = parse_options(args(&["chess", "--window-size", bad])).expect_err("invalid size");
+            assert!(err.contains("--window-size"), "error should name the flag: {err}");
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::time::Duration;
+
+use rsoderh_chess::{Color, Game, Piece, PieceKind, Position, Slot};
+
+use puhl_gui::protocol::{parse_fen, serialize_board};
+use crate::notation::piece_letter;
+use crate::rules::result_tag;
+use crate::state::{check_kings, game_from, ChessState};
+
+// Finished games are exported here, in the working directory
+pub const PGN_FILE: &str = "game.pgn";
+
+// The game as PGN. Moves are written in the long algebraic notation of the
+// move history (`Ng1-f3`, `e4xd5`), which most PGN readers accept. A game that
+// didn't start from the standard position gets a FEN tag; castling and en
+// passant aren't tracked, so those fields are always `-`. With `times`, each
+// move is followed by the time its side spent on it, as a `{[%emt h:mm:ss]}`
+// comment.
+pub fn to_pgn(state: &ChessState, times: bool) -> String {
+    let result = result_tag(state.outcome);
+    let mut pgn = String::new();
+    for (tag, value) in [
+        ("Event", "Casual game"),
+        ("Site", "puhl-gui"),
+        ("Date", "????.??.??"),
+        ("Round", "-"),
+        ("White", "White"),
+        ("Black", "Black"),
+        ("Result", result),
+    ] {
+        pgn += &format!("[{tag} \"{value}\"]\n");
+    }
+
+    let (board, turn, fullmove) = state.start();
+    let standard = Game::new_standard();
+    if board != standard.board() || turn != standard.turn || fullmove != 1 {
+        let turn = if turn == Color::White { "w" } else { "b" };
+        pgn += "[SetUp \"1\"]\n";
+        pgn += &format!("[FEN \"{} {turn} - - 0 {fullmove}\"]\n", serialize_board(board));
+    }
+    pgn += "\n";
+
+    let mut movetext = state.movetext();
+    if times {
+        for (token, thinking) in movetext.iter_mut().zip(state.thinking_times()) {
+            *token += &format!(" {{[%emt {}]}}", format_emt(thinking));
+        }
+    }
+    movetext.push(result.to_string());
+    pgn += &movetext.join(" ");
+    pgn += "\n";
+    pgn
+}
+
+// Elapsed move time as `h:mm:ss`
+fn format_emt(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Play the mainline of a PGN, as written by `to_pgn`. Moves have to be in our
+// long algebraic notation (`e2-e4`, `Ng1-f3`, `e7xd8=Q+`, `O-O`), not SAN, and
+// are checked against the legal moves of each position. A FEN tag sets the
+// starting position. Comments, such as the `{[%emt ...]}` move times, and
+// move numbers are skipped, and the result token ends the movetext.
+pub fn from_pgn(input: &str) -> Result<ChessState, String> {
+    let mut state = ChessState::default();
+    let mut movetext = String::new();
+    for line in input.lines() {
+        let line = line.trim();
+        match line.strip_prefix('[') {
+            Some(tag) => {
+                if let Some(fen) = tag.strip_prefix("FEN \"").and_then(|tag| tag.strip_suffix("\"]")) {
+                    state = state_from_fen(fen)?;
+                }
+            }
+            None => {
+                movetext += line;
+                movetext.push(' ');
+            }
+        }
+    }
+
+    for token in strip_comments(&movetext)?.split_whitespace() {
+        if matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*") {
+            break;
+        }
+        // Move numbers, `12.` or `12...`, which may run into the move itself
+        let token = token.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start_matches('.');
+        if token.is_empty() {
+            continue;
+        }
+
+        let side = if state.turn() == Color::White { "" } else { ".." };
+        let at = format!("Move {}.{side} {token}", state.fullmove());
+        if state.outcome.is_some() {
+            return Err(format!("{at}: the game has already ended"));
+        }
+        let (src, dest, prom) = parse_long_algebraic(&state, token)
+            .ok_or_else(|| format!("{at}: expected a move like e2-e4, Ng1-f3 or O-O"))?;
+        if !state.legal_moves(src).contains(&dest) {
+            return Err(format!("{at}: illegal move"));
+        }
+        match (state.promotes(src, dest), prom) {
+            (Some(_), None) => return Err(format!("{at}: missing the promotion piece, e.g. =Q")),
+            (None, Some(_)) => return Err(format!("{at}: only pawns reaching the last rank promote")),
+            _ => {}
+        }
+        state.apply((src, dest), prom).map_err(|why| format!("{at}: illegal move ({why})"))?;
+    }
+    Ok(state)
+}
+
+// Starting position from a FEN tag, as `to_pgn` writes it. Castling rights and
+// the en passant square aren't tracked, so only the placement, side to move
+// and fullmove number are read.
+fn state_from_fen(fen: &str) -> Result<ChessState, String> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    let board = parse_fen(fields.first().copied().unwrap_or("")).map_err(|e| format!("Invalid FEN tag: {e:?}"))?;
+    let turn = match fields.get(1).copied() {
+        Some("w") => Color::White,
+        Some("b") => Color::Black,
+        _ => return Err(format!("Invalid FEN tag '{fen}': expected w or b to move")),
+    };
+    let fullmove = match fields.get(5) {
+        Some(number) => {
+            let number = number.parse().ok().filter(|&n: &u32| n > 0);
+            number.ok_or_else(|| format!("Invalid FEN tag '{fen}': bad fullmove number"))?
+        }
+        None => 1,
+    };
+    check_kings(&board).map_err(|e| format!("Invalid FEN tag: {e}"))?;
+    Ok(ChessState::new(game_from(board, turn)).with_fullmove(fullmove))
+}
+
+// Movetext without its `{...}` comments
+fn strip_comments(movetext: &str) -> Result<String, String> {
+    let mut stripped = String::new();
+    let mut rest = movetext;
+    while let Some(start) = rest.find('{') {
+        stripped += &rest[..start];
+        let end = rest[start..].find('}').ok_or("Unterminated comment in the movetext")?;
+        stripped.push(' ');
+        rest = &rest[start + end + 1..];
+    }
+    stripped += rest;
+    Ok(stripped)
+}
+
+// Squares and promotion piece of a move in our long algebraic notation, if
+// it is one and the piece letter matches the piece on the source square
+fn parse_long_algebraic(state: &ChessState, token: &str) -> Option<(Position, Position, Option<PieceKind>)> {
+    let token = token.trim_end_matches(['+', '#', '!', '?']);
+    let rank = if state.turn() == Color::White { "1" } else { "8" };
+    let castle = |dest: &str| {
+        Some((Position::parse(&format!("e{rank}"))?, Position::parse(&format!("{dest}{rank}"))?, None))
+    };
+    match token {
+        "O-O" => return castle("g"),
+        "O-O-O" => return castle("c"),
+        _ => {}
+    }
+
+    let (token, prom) = match token.split_once('=') {
+        Some((token, letter)) => {
+            let kind = [PieceKind::Knight, PieceKind::Bishop, PieceKind::Rook, PieceKind::Queen]
+                .into_iter()
+                .find(|&kind| piece_letter(kind) == letter)?;
+            (token, Some(kind))
+        }
+        None => (token, None),
+    };
+    // An optional piece letter, then e.g. `g1-f3`
+    let split = token.len().checked_sub(5).filter(|&split| split <= 1 && token.is_ascii())?;
+    let (letter, squares) = token.split_at(split);
+    if !matches!(&squares[2..3], "-" | "x") {
+        return None;
+    }
+    let src = Position::parse(&squares[..2])?;
+    let dest = Position::parse(&squares[3..])?;
+    match state.board().at_position(src) {
+        Slot::Occupied(Piece { kind, .. }) if piece_letter(kind) == letter => Some((src, dest, prom)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::{play, state_with};
+    use rsoderh_chess::PieceKind;
+
+    // Kings and a pawn set up with Black to move at move 12, then Ke8-d8 Ke1-d1
+    fn custom_start() -> ChessState {
+        let pieces = [
+            ("e1", Color::White, PieceKind::King),
+            ("e8", Color::Black, PieceKind::King),
+            ("a2", Color::White, PieceKind::Pawn),
+        ];
+        let mut state = state_with(&pieces, Color::Black).with_fullmove(12);
+        play(&mut state, &[("e8", "d8"), ("e1", "d1")]);
+        state
+    }
+
+    #[test]
+    fn standard_game_movetext() {
+        let mut state = ChessState::default();
+        play(&mut state, &[("e2", "e4"), ("e7", "e5"), ("g1", "f3")]);
+
+        let pgn = to_pgn(&state, false);
+        assert!(pgn.starts_with("[Event \"Casual game\"]\n"), "{pgn}");
+        assert!(pgn.contains("[Result \"*\"]\n"), "{pgn}");
+        assert!(!pgn.contains("[FEN"), "standard start needs no FEN tag: {pgn}");
+        assert!(pgn.ends_with("\n\n1. e2-e4 e7-e5 2. Ng1-f3 *\n"), "{pgn}");
+    }
+
+    #[test]
+    fn custom_start_gets_a_fen_tag() {
+        let state = custom_start();
+
+        let pgn = to_pgn(&state, false);
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/P7/4K3 b - - 0 12\"]\n"), "{pgn}");
+        assert!(pgn.ends_with("12... Ke8-d8 13. Ke1-d1 *\n"), "{pgn}");
+    }
+
+    #[test]
+    fn moves_can_carry_their_times() {
+        let mut state = ChessState::default();
+        play(&mut state, &[("e2", "e4"), ("e7", "e5")]);
+
+        let pgn = to_pgn(&state, true);
+        assert!(pgn.ends_with("\n\n1. e2-e4 {[%emt 0:00:00]} e7-e5 {[%emt 0:00:00]} *\n"), "{pgn}");
+        assert_eq!(format_emt(Duration::from_secs(3725)), "1:02:05");
+    }
+
+    #[test]
+    fn exported_games_import_again() {
+        let mut state = ChessState::default();
+        play(&mut state, &[("e2", "e4"), ("d7", "d5"), ("e4", "d5"), ("g8", "f6"), ("g1", "f3")]);
+
+        let imported = from_pgn(&to_pgn(&state, true)).expect("our own PGN");
+        assert_eq!(imported.moves().collect::<Vec<_>>(), state.moves().collect::<Vec<_>>());
+        assert_eq!(imported.fen(), state.fen());
+    }
+
+    #[test]
+    fn fen_tag_sets_the_start() {
+        let state = custom_start();
+
+        let pgn = to_pgn(&state, false);
+        let imported = from_pgn(&pgn).unwrap_or_else(|e| panic!("{e}: {pgn}"));
+        assert_eq!(imported.start(), state.start());
+        assert_eq!(imported.fen(), state.fen());
+        assert_eq!(imported.fullmove(), 13);
+    }
+
+    #[test]
+    fn import_errors_name_the_move() {
+        let err = from_pgn("1. e4 e5 *").err().expect("SAN isn't supported");
+        assert!(err.starts_with("Move 1. e4:"), "{err}");
+
+        let err = from_pgn("1. e2-e4 e7-e5 2. Ng1-h1 *").err().expect("own rook on h1");
+        assert_eq!(err, "Move 2. Ng1-h1: illegal move");
+
+        let err = from_pgn("1. e2-e4 Ng8-f6 {unfinished").err().expect("open comment");
+        assert_eq!(err, "Unterminated comment in the movetext");
+
+        let err = from_pgn("1. e2-e4 Bg8-f6 *").err().expect("a knight on g8");
+        assert!(err.starts_with("Move 1... Bg8-f6:"), "{err}");
+    }
+}
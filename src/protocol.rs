@@ -1,7 +1,11 @@
+use std::fmt;
+
 use rsoderh_chess::{Board, Color, Piece, PieceKind, Position, Slot};
 
 const BOARD_LEN: usize = 8;
 const BOARD_SIZE: usize = 64;
+// Every frame on the wire is exactly this many bytes
+pub const FRAME_LEN: usize = 128;
 
 #[derive(PartialEq, Debug)]
 pub enum ParseError {
@@ -12,16 +16,74 @@ pub enum ParseError {
     InvalidMoveFormat,
     InvalidGameState,
 
-    InvalidFENChar,
+    // `rank` is the chess rank (1-8) of the field holding `ch`
+    InvalidFENChar { ch: char, rank: usize },
+    // Not eight ranks
     InvalidFENLength,
+    // A rank whose pieces and empty squares don't add up to eight files
+    InvalidFENRank { rank: usize },
+    // A full FEN where only the piece placement belongs, e.g. a board field
+    // followed by the side to move and castling rights
+    ExtraFENFields,
+
+    WrongLength,
+    InvalidTakeback,
+    InvalidRematch,
+    // A resync whose side to move or halfmove count can't be read
+    InvalidResync,
+    // A frame byte outside ASCII
+    NonAscii,
 }
 
 #[derive(PartialEq, Debug)]
 pub enum SerializeError {
     InvalidPromPiece,
     TooLongQuitMsg,
+    // A ':' in the quit message would split it into extra fields
+    ColonInQuitMsg,
+    // The assembled frame is longer than 128 bytes before padding
+    Overflow,
+}
+
+// Readable descriptions, worded to follow e.g. "Received malformed data: "
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TooLong => write!(f, "the frame is longer than {FRAME_LEN} bytes"),
+            ParseError::UnknownMessageType => write!(f, "unknown message type"),
+            ParseError::WrongAmountOfFields => write!(f, "wrong number of fields"),
+            ParseError::InvalidMoveFormat => write!(f, "invalid move"),
+            ParseError::InvalidGameState => write!(f, "invalid game state"),
+            ParseError::InvalidFENChar { ch, rank } => {
+                write!(f, "invalid character '{ch}' on rank {rank} of the board")
+            }
+            ParseError::InvalidFENLength => write!(f, "the board doesn't have eight ranks"),
+            ParseError::InvalidFENRank { rank } => write!(f, "rank {rank} of the board doesn't have eight files"),
+            ParseError::ExtraFENFields => write!(f, "the board field holds more than the piece placement"),
+            ParseError::WrongLength => write!(f, "wrong message length"),
+            ParseError::InvalidTakeback => write!(f, "invalid takeback message"),
+            ParseError::InvalidRematch => write!(f, "invalid rematch message"),
+            ParseError::InvalidResync => write!(f, "invalid resync message"),
+            ParseError::NonAscii => write!(f, "the frame isn't plain ASCII"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::InvalidPromPiece => write!(f, "a pawn can't promote to that piece"),
+            SerializeError::TooLongQuitMsg => write!(f, "the quit message is too long"),
+            SerializeError::ColonInQuitMsg => write!(f, "the quit message contains a ':'"),
+            SerializeError::Overflow => write!(f, "the message doesn't fit in {FRAME_LEN} bytes"),
+        }
+    }
 }
 
+impl std::error::Error for SerializeError {}
+
 #[derive(PartialEq, Debug)]
 pub enum GameState {
     Ongoing,
@@ -30,24 +92,83 @@ pub enum GameState {
     WinBlack,
 }
 
+// Kind of move, so the receiver can check special moves were understood the same way.
+// Written as the first padding character only when set. The spec wants the
+// padding all '0', so the game never sets it and doesn't check tags it receives.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum MoveType {
+    Normal,
+    EnPassant,
+    CastleKingside,
+    CastleQueenside,
+}
+
+// The position as the sender has it, exchanged after reconnecting so both
+// sides can check they still agree
+#[derive(PartialEq, Debug)]
+pub struct MessageResync {
+    pub board: Board,
+    pub turn: Color,
+    // Halfmoves played so far, like in a takeback request
+    pub halfmoves: u16,
+}
+
 #[derive(PartialEq, Debug)]
 pub struct MessageMove {
     pub board: Board,
     pub mv: (Position, Position),
     pub prom_piece: Option<PieceKind>,
     pub game_state: GameState,
+    pub move_type: Option<MoveType>,
+}
+
+// Classify `mv` from the board before it is played
+pub fn classify_move(board: &Board, mv: (Position, Position)) -> MoveType {
+    let (src, dst) = mv;
+    let Slot::Occupied(piece) = board.at_position(src) else {
+        return MoveType::Normal;
+    };
+
+    let file_delta = dst.column() as i8 - src.column() as i8;
+    match piece.kind {
+        PieceKind::King if file_delta == 2 => MoveType::CastleKingside,
+        PieceKind::King if file_delta == -2 => MoveType::CastleQueenside,
+        // A pawn only moves diagonally onto an empty square when capturing en passant
+        PieceKind::Pawn if file_delta != 0 && matches!(board.at_position(dst), Slot::Empty) => MoveType::EnPassant,
+        _ => MoveType::Normal,
+    }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum Message {
     Quit(String),
     Move(MessageMove),
+    // Ask to undo the last move. Carries the number of halfmoves the sender has
+    // played, so both sides agree which move is taken back.
+    TakebackRequest(u16),
+    TakebackResponse(bool),
+    // Keepalive: a Ping is answered with a Pong, so a silent peer can be told from a dead one
+    Ping,
+    Pong,
+    // The sender's clock ran out, so it loses on time
+    Flag,
+    // Offer a draw, or accept the opponent's outstanding offer. Moving instead
+    // declines it.
+    DrawOffer,
+    // The sender resigns the game
+    Resign,
+    // Once the game is over, ask to play again with colors swapped
+    Rematch,
+    RematchResponse(bool),
+    // Sent by both sides once a dropped connection is made again
+    Resync(MessageResync),
 }
 
 pub fn parse(message: &str) -> Result<Message, ParseError> {
-    if message.len() > 128 {
+    if message.len() > FRAME_LEN {
         return Err(ParseError::TooLong);
     }
+    let frame_len = message.len();
 
     let mut message = message.split(":");
     let msg_id= message.next();
@@ -60,8 +181,25 @@ pub fn parse(message: &str) -> Result<Message, ParseError> {
     let message = message.as_slice();
     
     match msg_id {
+        // Every frame on the wire is padded to the full length
+        "ChessMOVE" | "ChessQUIT" | "ChessTAKEBACK" | "ChessTAKEBACKREPLY" | "ChessPING" | "ChessPONG"
+        | "ChessFLAG" | "ChessDRAW" | "ChessRESIGN" | "ChessREMATCH" | "ChessREMATCHREPLY" | "ChessRESYNC"
+            if frame_len != FRAME_LEN =>
+        {
+            Err(ParseError::WrongLength)
+        }
         "ChessMOVE" => Ok(Message::Move(parse_message_move(message)?)),
         "ChessQUIT" => Ok(Message::Quit(parse_message_quit(message)?)),
+        "ChessTAKEBACK" => Ok(Message::TakebackRequest(parse_message_takeback(message)?)),
+        "ChessTAKEBACKREPLY" => Ok(Message::TakebackResponse(parse_message_takeback_reply(message)?)),
+        "ChessPING" => parse_message_empty(message).map(|_| Message::Ping),
+        "ChessPONG" => parse_message_empty(message).map(|_| Message::Pong),
+        "ChessFLAG" => parse_message_empty(message).map(|_| Message::Flag),
+        "ChessDRAW" => parse_message_empty(message).map(|_| Message::DrawOffer),
+        "ChessRESIGN" => parse_message_empty(message).map(|_| Message::Resign),
+        "ChessREMATCH" => parse_message_empty(message).map(|_| Message::Rematch),
+        "ChessREMATCHREPLY" => Ok(Message::RematchResponse(parse_message_rematch_reply(message)?)),
+        "ChessRESYNC" => Ok(Message::Resync(parse_message_resync(message)?)),
         _ => return Err(ParseError::UnknownMessageType),
     }
 }
@@ -70,6 +208,23 @@ pub fn serialize(message: &Message) -> Result<String, SerializeError> {
     match message {
         Message::Move(message) => serialize_move(&message),
         Message::Quit(str) => serialize_quit(str),
+        Message::TakebackRequest(halfmoves) => pad_frame(format!("ChessTAKEBACK:{halfmoves}:")),
+        Message::TakebackResponse(accepted) => {
+            pad_frame(format!("ChessTAKEBACKREPLY:{}:", if *accepted { '1' } else { '0' }))
+        }
+        Message::Ping => pad_frame("ChessPING:".to_string()),
+        Message::Pong => pad_frame("ChessPONG:".to_string()),
+        Message::Flag => pad_frame("ChessFLAG:".to_string()),
+        Message::DrawOffer => pad_frame("ChessDRAW:".to_string()),
+        Message::Resign => pad_frame("ChessRESIGN:".to_string()),
+        Message::Rematch => pad_frame("ChessREMATCH:".to_string()),
+        Message::RematchResponse(accepted) => {
+            pad_frame(format!("ChessREMATCHREPLY:{}:", if *accepted { '1' } else { '0' }))
+        }
+        Message::Resync(resync) => {
+            let turn = if resync.turn == Color::White { 'w' } else { 'b' };
+            pad_frame(format!("ChessRESYNC:{}:{turn}:{}:", serialize_board(&resync.board), resync.halfmoves))
+        }
     }
 }
 
@@ -107,56 +262,14 @@ fn serialize_move(message: &MessageMove) -> Result<String, SerializeError> {
         }
     }
 
-    fn serialize_board(board: &Board) -> String {
-        fn serialize_piece(piece: Piece) -> char {
-            let serialized_piece_kind = match piece.kind {
-                PieceKind::Pawn     => 'P',
-                PieceKind::Knight   => 'N',
-                PieceKind::Bishop   => 'B',
-                PieceKind::Rook     => 'R',
-                PieceKind::Queen    => 'Q',
-                PieceKind::King     => 'K',
-            };
-
-            if piece.color == Color::White { 
-                serialized_piece_kind
-            } else { 
-                serialized_piece_kind.to_ascii_lowercase()
-            }
+    fn serialize_move_type(message: &MessageMove) -> char {
+        match message.move_type {
+            None                            => '0',
+            Some(MoveType::Normal)          => 'N',
+            Some(MoveType::EnPassant)       => 'E',
+            Some(MoveType::CastleKingside)  => 'K',
+            Some(MoveType::CastleQueenside) => 'Q',
         }
-
-        (0..BOARD_LEN)
-            .map(|rank| {
-            let mut fen_rank: String = "".to_string();
-            let mut empty_count = 0;
-            for file in 0..BOARD_LEN {
-                // TODO: fix the ordering of this!!!
-                // Should not fail
-                let pos = Position::new(file as u8, rank as u8).unwrap();
-                match board.at_position(pos) {
-                    Slot::Occupied(piece) => {
-                        if empty_count > 0 {
-                            let chr = std::char::from_digit(empty_count, 10).unwrap();
-                            fen_rank.push(chr);
-                        }
-                        let piece_fen = serialize_piece(piece);
-                        fen_rank.push(piece_fen);
-                        empty_count = 0;
-                    },
-                    Slot::Empty => {
-                        empty_count += 1;
-                        continue;
-                    },
-                }
-            }
-            if empty_count > 0 {
-                let chr = std::char::from_digit(empty_count, 10).unwrap();
-                fen_rank.push(chr);
-            }
-            fen_rank
-        })
-        .collect::<Vec<_>>()
-        .join("/")
     }
 
     let serialized_msg_id  = "ChessMOVE";
@@ -166,25 +279,84 @@ fn serialize_move(message: &MessageMove) -> Result<String, SerializeError> {
     
     let mut serialized= [serialized_msg_id, &serialized_mv, serialized_game_state, &serialized_board].join(":");
     serialized += ":";
-    serialized += &"0".repeat(128 - serialized.len());
+    serialized.push(serialize_move_type(message));
+    pad_frame(serialized)
 
-    Ok(serialized)
+}
+
+// Piece placement field of a FEN string, from rank 8 down to rank 1
+pub fn serialize_board(board: &Board) -> String {
+    fn serialize_piece(piece: Piece) -> char {
+        let serialized_piece_kind = match piece.kind {
+            PieceKind::Pawn     => 'P',
+            PieceKind::Knight   => 'N',
+            PieceKind::Bishop   => 'B',
+            PieceKind::Rook     => 'R',
+            PieceKind::Queen    => 'Q',
+            PieceKind::King     => 'K',
+        };
 
+        if piece.color == Color::White { 
+            serialized_piece_kind
+        } else { 
+            serialized_piece_kind.to_ascii_lowercase()
+        }
+    }
+
+    (0..BOARD_LEN)
+        .rev()
+        .map(|rank| {
+        let mut fen_rank: String = "".to_string();
+        let mut empty_count = 0;
+        for file in 0..BOARD_LEN {
+            // Should not fail
+            let pos = Position::new(file as u8, rank as u8).unwrap();
+            match board.at_position(pos) {
+                Slot::Occupied(piece) => {
+                    if empty_count > 0 {
+                        let chr = std::char::from_digit(empty_count, 10).unwrap();
+                        fen_rank.push(chr);
+                    }
+                    let piece_fen = serialize_piece(piece);
+                    fen_rank.push(piece_fen);
+                    empty_count = 0;
+                },
+                Slot::Empty => {
+                    empty_count += 1;
+                    continue;
+                },
+            }
+        }
+        if empty_count > 0 {
+            let chr = std::char::from_digit(empty_count, 10).unwrap();
+            fen_rank.push(chr);
+        }
+        fen_rank
+    })
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+// Fill a short frame up to the full length with '0's
+fn pad_frame(mut frame: String) -> Result<String, SerializeError> {
+    let padding = FRAME_LEN.checked_sub(frame.len()).ok_or(SerializeError::Overflow)?;
+    frame += &"0".repeat(padding);
+    Ok(frame)
 }
 
 fn serialize_quit(str: &str) -> Result<String, SerializeError> {
-    let mut serialized = "ChessQUIT:".to_string() + str + ":";
-    if serialized.len() > 128{
-        return Err(SerializeError::TooLongQuitMsg);
+    if str.contains(':') {
+        return Err(SerializeError::ColonInQuitMsg);
     }
-    serialized += &"0".repeat(128 - serialized.len());
-    Ok(serialized)
+    let serialized = "ChessQUIT:".to_string() + str + ":";
+    pad_frame(serialized).map_err(|_| SerializeError::TooLongQuitMsg)
 }
 
 fn parse_message_move(message: &[&str]) -> Result<MessageMove, ParseError> {
     match *message {
-        [mv, game_state, board, _padding] => {
-            if mv.len() != 5 {
+        [mv, game_state, board, padding] => {
+            // Checking for ASCII first keeps the slicing below on char boundaries
+            if mv.len() != 5 || !mv.is_ascii() {
                 return Err(ParseError::InvalidMoveFormat);
             }
 
@@ -213,11 +385,21 @@ fn parse_message_move(message: &[&str]) -> Result<MessageMove, ParseError> {
             
             let board = parse_fen(board)?;
 
+            // Anything unrecognised is plain padding from a peer that doesn't tag moves
+            let move_type = match padding.chars().next() {
+                Some('N') => Some(MoveType::Normal),
+                Some('E') => Some(MoveType::EnPassant),
+                Some('K') => Some(MoveType::CastleKingside),
+                Some('Q') => Some(MoveType::CastleQueenside),
+                _ => None,
+            };
+
             Ok(MessageMove {
                 board,
                 game_state,
                 mv,
                 prom_piece,
+                move_type,
             })
         },
         _ => return Err(ParseError::WrongAmountOfFields),
@@ -231,11 +413,94 @@ fn parse_message_quit(message: &[&str]) -> Result<String, ParseError> {
     }
 }
 
-fn parse_fen(fen: &str) -> Result<Board, ParseError> {
+fn parse_message_takeback(message: &[&str]) -> Result<u16, ParseError> {
+    match *message {
+        [halfmoves, _padding] => halfmoves.parse().map_err(|_| ParseError::InvalidTakeback),
+        _ => Err(ParseError::WrongAmountOfFields),
+    }
+}
+
+fn parse_message_takeback_reply(message: &[&str]) -> Result<bool, ParseError> {
+    match *message {
+        ["1", _padding] => Ok(true),
+        ["0", _padding] => Ok(false),
+        [_, _padding] => Err(ParseError::InvalidTakeback),
+        _ => Err(ParseError::WrongAmountOfFields),
+    }
+}
+
+fn parse_message_rematch_reply(message: &[&str]) -> Result<bool, ParseError> {
+    match *message {
+        ["1", _padding] => Ok(true),
+        ["0", _padding] => Ok(false),
+        [_, _padding] => Err(ParseError::InvalidRematch),
+        _ => Err(ParseError::WrongAmountOfFields),
+    }
+}
+
+fn parse_message_resync(message: &[&str]) -> Result<MessageResync, ParseError> {
+    match *message {
+        [board, turn, halfmoves, _padding] => {
+            let turn = match turn {
+                "w" => Color::White,
+                "b" => Color::Black,
+                _ => return Err(ParseError::InvalidResync),
+            };
+            let halfmoves = halfmoves.parse().map_err(|_| ParseError::InvalidResync)?;
+            Ok(MessageResync { board: parse_fen(board)?, turn, halfmoves })
+        }
+        _ => Err(ParseError::WrongAmountOfFields),
+    }
+}
+
+// Every square where `a` and `b` differ, with the slot on each board, in
+// a1, b1, ..., h8 order
+pub fn board_diff(a: &Board, b: &Board) -> Vec<(Position, Slot, Slot)> {
+    let same = |x: Slot, y: Slot| match (x, y) {
+        (Slot::Empty, Slot::Empty) => true,
+        (Slot::Occupied(p), Slot::Occupied(q)) => p == q,
+        _ => false,
+    };
+
+    let mut diff = Vec::new();
+    for rank in 0..BOARD_LEN as u8 {
+        for file in 0..BOARD_LEN as u8 {
+            let position = Position::new(file, rank).unwrap();
+            let (x, y) = (a.at_position(position), b.at_position(position));
+            if !same(x, y) {
+                diff.push((position, x, y));
+            }
+        }
+    }
+    diff
+}
+
+// Messages without fields, which are only the ID and padding
+fn parse_message_empty(message: &[&str]) -> Result<(), ParseError> {
+    match *message {
+        [_padding] => Ok(()),
+        _ => Err(ParseError::WrongAmountOfFields),
+    }
+}
+
+// Parse the piece placement field of a FEN string
+pub fn parse_fen(fen: &str) -> Result<Board, ParseError> {
+    // The other FEN fields follow the placement after a space
+    if fen.contains(char::is_whitespace) {
+        return Err(ParseError::ExtraFENFields);
+    }
     let mut board = Board::new_empty();
     
     let mut index: usize = BOARD_SIZE;
-    for rank in fen.split("/") {
+    for (field, rank) in fen.split("/").enumerate() {
+        if field >= BOARD_LEN {
+            return Err(ParseError::InvalidFENLength);
+        }
+        let files: usize = rank.chars().map(|chr| chr.to_digit(10).unwrap_or(1) as usize).sum();
+        if files != BOARD_LEN {
+            return Err(ParseError::InvalidFENRank { rank: BOARD_LEN - field });
+        }
+
         for chr in rank.chars().rev() {
             if let Some(skips) = chr.to_digit(10) {
                 index -= skips as usize;
@@ -243,12 +508,6 @@ fn parse_fen(fen: &str) -> Result<Board, ParseError> {
             }
             index -= 1;
 
-            // Index wraps around to usize::MAX when "below zero"
-            if index >= BOARD_SIZE {
-                // FEN string too long
-                return Err(ParseError::InvalidFENLength);
-            }
-
             let piece_kind = match chr.to_ascii_uppercase() {
                 'P' => PieceKind::Pawn,
                 'N' => PieceKind::Knight,
@@ -256,7 +515,7 @@ fn parse_fen(fen: &str) -> Result<Board, ParseError> {
                 'R' => PieceKind::Rook,
                 'Q' => PieceKind::Queen,
                 'K' => PieceKind::King,
-                _ => return Err(ParseError::InvalidFENChar),
+                _ => return Err(ParseError::InvalidFENChar { ch: chr, rank: BOARD_LEN - field }),
             };
 
             let piece_color = match chr.is_uppercase() {
@@ -265,7 +524,7 @@ fn parse_fen(fen: &str) -> Result<Board, ParseError> {
             };
 
             let rank = index / BOARD_LEN;
-            let file = BOARD_LEN - (index % BOARD_LEN) - 1;
+            let file = index % BOARD_LEN;
 
             assert!(rank < BOARD_LEN);
             assert!(file < BOARD_LEN);
@@ -286,7 +545,7 @@ fn parse_fen(fen: &str) -> Result<Board, ParseError> {
         }
     }
     if index != 0 {
-        // FEN string too short
+        // Fewer than eight ranks
         return Err(ParseError::InvalidFENLength)
     }
 
@@ -301,6 +560,11 @@ mod tests {
         s.chars().all(|c| c == '0')
     }
 
+    // Pad a hand-written frame with '0's to the full 128 bytes
+    fn pad(msg: &str) -> String {
+        format!("{msg:0<128}")
+    }
+
     // Taken from the spec: https://github.com/INDA25PlusPlus/chesstp-spec
     fn move_padding_len(board_len: usize) -> usize {
         128 - 9 - 1 - 5 - 1 - 3 - 1 - board_len - 1
@@ -318,6 +582,7 @@ mod tests {
             mv: (src, dst),
             prom_piece: None,
             game_state: GameState::Ongoing,
+            move_type: None,
         });
 
         let s = serialize(&msg).expect("serialize move");
@@ -364,6 +629,7 @@ mod tests {
             mv: (src, dst),
             prom_piece: Some(PieceKind::Queen),
             game_state: GameState::WinWhite,
+            move_type: None,
         });
 
         let s = serialize(&msg).expect("serialize move");
@@ -388,12 +654,106 @@ mod tests {
             mv: (src, dst),
             prom_piece: Some(PieceKind::King), // invalid promotion piece
             game_state: GameState::Ongoing,
+            move_type: None,
         });
 
         let err = serialize(&msg).expect_err("invalid promotion piece must error");
         assert!(matches!(err, SerializeError::InvalidPromPiece));
     }
 
+    #[test]
+    fn move_type_round_trips_in_padding() {
+        let src = Position::new(4, 0).unwrap(); // E1
+        let dst = Position::new(6, 0).unwrap(); // G1
+
+        for move_type in [
+            None,
+            Some(MoveType::Normal),
+            Some(MoveType::EnPassant),
+            Some(MoveType::CastleKingside),
+            Some(MoveType::CastleQueenside),
+        ] {
+            let msg = Message::Move(MessageMove {
+                board: Board::new_empty(),
+                mv: (src, dst),
+                prom_piece: None,
+                game_state: GameState::Ongoing,
+                move_type,
+            });
+
+            let s = serialize(&msg).expect("serialize move");
+            assert_eq!(s.len(), 128, "tag must fit in the frame");
+            let parts: Vec<&str> = s.split(':').collect();
+            assert_eq!(parts.len(), 5, "tag must not add a field");
+
+            match parse(&s).expect("parse tagged move") {
+                Message::Move(mm) => assert_eq!(mm.move_type, move_type),
+                _ => panic!("expected Message::Move"),
+            }
+        }
+    }
+
+    #[test]
+    fn classify_castling_and_en_passant() {
+        let mut board = Board::new_empty();
+        let pos = |s: &str| Position::parse(s).unwrap();
+        let place = |board: &mut Board, s: &str, color, kind| {
+            *board.at_position_mut(pos(s)) = Slot::Occupied(Piece { color, kind });
+        };
+        place(&mut board, "e1", Color::White, PieceKind::King);
+        place(&mut board, "e5", Color::White, PieceKind::Pawn);
+        place(&mut board, "d5", Color::Black, PieceKind::Pawn);
+        place(&mut board, "f6", Color::Black, PieceKind::Knight);
+
+        assert_eq!(classify_move(&board, (pos("e1"), pos("g1"))), MoveType::CastleKingside);
+        assert_eq!(classify_move(&board, (pos("e1"), pos("c1"))), MoveType::CastleQueenside);
+        assert_eq!(classify_move(&board, (pos("e1"), pos("f1"))), MoveType::Normal);
+        assert_eq!(classify_move(&board, (pos("e5"), pos("d6"))), MoveType::EnPassant);
+        assert_eq!(classify_move(&board, (pos("e5"), pos("f6"))), MoveType::Normal);
+        assert_eq!(classify_move(&board, (pos("e5"), pos("e6"))), MoveType::Normal);
+    }
+
+    #[test]
+    fn parse_ignores_unknown_padding() {
+        let msg = format!("ChessMOVE:a2a40:0-0:{}:x", "8/8/8/8/8/8/8/8");
+        match parse(&pad(&msg)) {
+            Ok(Message::Move(m)) => assert_eq!(m.move_type, None),
+            _ => panic!("expected valid Move"),
+        }
+    }
+
+    #[test]
+    fn fen_ranks_run_from_eight_to_one() {
+        let board = rsoderh_chess::Game::new_standard().board().clone();
+        assert_eq!(serialize_board(&board), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR");
+
+        let parsed = parse_fen("4k3/8/8/8/8/8/8/R3K3").expect("valid FEN");
+        let at = |s: &str| match parsed.at_position(Position::parse(s).unwrap()) {
+            Slot::Occupied(piece) => Some(piece),
+            Slot::Empty => None,
+        };
+        assert_eq!(at("a1"), Some(Piece { color: Color::White, kind: PieceKind::Rook }));
+        assert_eq!(at("e1"), Some(Piece { color: Color::White, kind: PieceKind::King }));
+        assert_eq!(at("e8"), Some(Piece { color: Color::Black, kind: PieceKind::King }));
+        assert_eq!(at("h1"), None);
+    }
+
+    #[test]
+    fn starting_position_round_trips_in_a_move_frame() {
+        let start = rsoderh_chess::Game::new_standard().board().clone();
+        let message = Message::Move(MessageMove {
+            board: start.clone(),
+            mv: (Position::parse("e2").unwrap(), Position::parse("e4").unwrap()),
+            prom_piece: None,
+            game_state: GameState::Ongoing,
+            move_type: Some(MoveType::Normal),
+        });
+        let frame = serialize(&message).expect("serialize move");
+        let Ok(Message::Move(parsed)) = parse(&frame) else { panic!("expected a Move: {frame}") };
+
+        assert_eq!(board_diff(&start, &parsed.board), Vec::new());
+    }
+
     #[test]
     fn serialize_quit_empty_message() {
         let s = serialize_quit("").expect("serialize quit");
@@ -440,11 +800,19 @@ mod tests {
         assert!(matches!(err, SerializeError::TooLongQuitMsg));
     }
 
+    #[test]
+    fn over_length_frame_is_an_error() {
+        assert_eq!(pad_frame("X".repeat(FRAME_LEN + 1)), Err(SerializeError::Overflow));
+        assert_eq!(pad_frame("X".repeat(FRAME_LEN)).map(|frame| frame.len()), Ok(FRAME_LEN));
+        // The longest quit message still fits: 10 + 117 + 1 bytes
+        assert_eq!(serialize_quit(&"X".repeat(117)).map(|frame| frame.len()), Ok(FRAME_LEN));
+    }
+
     #[test]
     fn parse_quit_missing_padding_is_error() {
-        // Missing trailing ':' + padding; total length < 128
+        // Missing trailing ':' before the padding
         let bad = "ChessQUIT:Bye";
-        let res = parse(bad);
+        let res = parse(&pad(bad));
         assert_eq!(res, Err(ParseError::WrongAmountOfFields));
     }
 
@@ -452,7 +820,7 @@ mod tests {
     fn parse_quit_with_colon_in_optional_message_is_error() {
         // Spec says: optional message must not contain ':'
         let bad = "ChessQUIT:hello:world:0";
-        let res = parse(bad);
+        let res = parse(&pad(bad));
         assert_eq!(res, Err(ParseError::WrongAmountOfFields));
     }
 
@@ -461,16 +829,33 @@ mod tests {
         let fen = "8/8/8/8/8/8/8/8";
         let msg = format!("ChessMOVE:a2a40:0-0:{}:x", fen);
 
-        let result = parse(&msg);
+        let result = parse(&pad(&msg));
         assert!(matches!(result, Ok(Message::Move(_))));
     }
 
+    #[test]
+    fn parse_move_with_wrong_amount_of_fields() {
+        let fen = "8/8/8/8/8/8/8/8";
+        // The padding runs straight into the board without its ':'
+        let three = format!("ChessMOVE:a2a40:0-0:{fen}");
+        assert_eq!(parse(&pad(&three)), Err(ParseError::WrongAmountOfFields));
+
+        let five = format!("ChessMOVE:a2a40:0-0:{fen}:N:");
+        assert_eq!(parse(&pad(&five)), Err(ParseError::WrongAmountOfFields));
+    }
+
+    #[test]
+    fn serialize_quit_rejects_colons() {
+        let err = serialize_quit("bye: see you").expect_err("':' would add a field");
+        assert_eq!(err, SerializeError::ColonInQuitMsg);
+    }
+
     #[test]
     fn parse_valid_move_with_promotion() {
         let fen = "8/8/8/8/8/8/8/8";
         let msg = format!("ChessMOVE:a7a8Q:1-0:{}:x", fen);
 
-        let result = parse(&msg);
+        let result = parse(&pad(&msg));
         match result {
             Ok(Message::Move(m)) => {
                 assert_eq!(m.prom_piece, Some(PieceKind::Queen));
@@ -500,7 +885,7 @@ mod tests {
         let fen = "8/8/8/8/8/8/8/8";
         let msg = format!("ChessMOVE:a2b:0-0:{}:x", fen);
 
-        let result = parse(&msg);
+        let result = parse(&pad(&msg));
         assert_eq!(result, Err(ParseError::InvalidMoveFormat));
     }
 
@@ -509,7 +894,7 @@ mod tests {
         let fen = "8/8/8/8/8/8/8/8";
         let msg = format!("ChessMOVE:a2a40:weird:{}:x", fen);
 
-        let result = parse(&msg);
+        let result = parse(&pad(&msg));
         assert_eq!(result, Err(ParseError::InvalidGameState));
     }
 
@@ -519,8 +904,14 @@ mod tests {
         let fen = "8/8/8/8/8/8/8/7Z";
         let msg = format!("ChessMOVE:a2a40:0-0:{}:x", fen);
 
-        let result = parse(&msg);
-        assert_eq!(result, Err(ParseError::InvalidFENChar));
+        let result = parse(&pad(&msg));
+        assert_eq!(result, Err(ParseError::InvalidFENChar { ch: 'Z', rank: 1 }));
+    }
+
+    #[test]
+    fn parse_fen_reports_the_rank_of_a_bad_char() {
+        let result = parse_fen("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR");
+        assert_eq!(result, Err(ParseError::InvalidFENChar { ch: 'x', rank: 7 }));
     }
 
     #[test]
@@ -529,7 +920,257 @@ mod tests {
         let fen = "8/8/8/8/8/8/8";
         let msg = format!("ChessMOVE:a2a40:0-0:{}:x", fen);
 
-        let result = parse(&msg);
+        let result = parse(&pad(&msg));
         assert_eq!(result, Err(ParseError::InvalidFENLength));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_fen_checks_each_rank() {
+        // Nine files on the seventh rank, with the board still 64 squares in total
+        let result = parse_fen("rnbqkbnr/ppppppppp/7/8/8/8/PPPPPPPP/RNBQKBNR");
+        assert_eq!(result, Err(ParseError::InvalidFENRank { rank: 7 }));
+
+        // Seven files on the fourth rank
+        let result = parse_fen("rnbqkbnr/pppppppp/8/8/7/8/PPPPPPPP/RNBQKBNR");
+        assert_eq!(result, Err(ParseError::InvalidFENRank { rank: 4 }));
+
+        assert_eq!(parse_fen("8/8/8/8/8/8/8/8/8"), Err(ParseError::InvalidFENLength), "nine ranks");
+    }
+
+    #[test]
+    fn parse_rejects_short_frames() {
+        let fen = "8/8/8/8/8/8/8/8";
+        let short_move = format!("ChessMOVE:a2a40:0-0:{}:x", fen);
+        assert_eq!(parse(&short_move), Err(ParseError::WrongLength));
+        assert_eq!(parse("ChessQUIT:Bye:"), Err(ParseError::WrongLength));
+
+        let one_short = &pad(&short_move)[..127];
+        assert_eq!(parse(one_short), Err(ParseError::WrongLength));
+        assert!(parse(&pad(&short_move)).is_ok());
+    }
+
+    #[test]
+    fn parse_checks_message_id_before_length() {
+        assert_eq!(parse("ChessHELLO:"), Err(ParseError::UnknownMessageType));
+    }
+
+    #[test]
+    fn takeback_messages_round_trip() {
+        for msg in [
+            Message::TakebackRequest(0),
+            Message::TakebackRequest(37),
+            Message::TakebackResponse(true),
+            Message::TakebackResponse(false),
+        ] {
+            let s = serialize(&msg).expect("serialize takeback");
+            assert_eq!(s.len(), 128);
+            assert_eq!(parse(&s), Ok(msg));
+        }
+    }
+
+    #[test]
+    fn takeback_wire_format() {
+        let s = serialize(&Message::TakebackRequest(12)).expect("serialize takeback");
+        let parts: Vec<&str> = s.split(':').collect();
+        assert_eq!(parts[..2], ["ChessTAKEBACK", "12"]);
+        assert!(is_all_zeros(parts[2]));
+
+        let s = serialize(&Message::TakebackResponse(true)).expect("serialize reply");
+        assert!(s.starts_with("ChessTAKEBACKREPLY:1:"));
+    }
+
+    #[test]
+    fn board_diff_lists_changed_squares() {
+        let start = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR").unwrap();
+        assert!(board_diff(&start, &start).is_empty());
+
+        let after = parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").unwrap();
+        let diff = board_diff(&start, &after);
+        assert_eq!(diff.len(), 2);
+        let pawn = Piece { color: Color::White, kind: PieceKind::Pawn };
+        assert!(matches!(diff[0], (p, Slot::Occupied(x), Slot::Empty) if p == Position::new(4, 1).unwrap() && x == pawn));
+        assert!(matches!(diff[1], (p, Slot::Empty, Slot::Occupied(x)) if p == Position::new(4, 3).unwrap() && x == pawn));
+
+        // A different piece on the same square counts too
+        let swapped = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBKQBNR").unwrap();
+        let squares: Vec<_> = board_diff(&start, &swapped).into_iter().map(|(p, _, _)| p).collect();
+        assert_eq!(squares, [Position::new(3, 0).unwrap(), Position::new(4, 0).unwrap()]);
+    }
+
+    #[test]
+    fn ping_and_pong_round_trip() {
+        for msg in [Message::Ping, Message::Pong] {
+            let s = serialize(&msg).expect("serialize keepalive");
+            assert_eq!(s.len(), 128);
+            assert_eq!(parse(&s), Ok(msg));
+        }
+
+        let s = serialize(&Message::Ping).unwrap();
+        assert!(s.starts_with("ChessPING:"));
+        assert!(is_all_zeros(&s["ChessPING:".len()..]));
+        assert_eq!(parse(&pad("ChessPONG:0:0")), Err(ParseError::WrongAmountOfFields));
+        assert_eq!(parse("ChessPING:"), Err(ParseError::WrongLength));
+    }
+
+    #[test]
+    fn flag_round_trip() {
+        let s = serialize(&Message::Flag).expect("serialize flag");
+        assert_eq!(s.len(), 128);
+        assert!(s.starts_with("ChessFLAG:"));
+        assert_eq!(parse(&s), Ok(Message::Flag));
+        assert_eq!(parse(&pad("ChessFLAG:1:")), Err(ParseError::WrongAmountOfFields));
+    }
+
+    #[test]
+    fn draw_offer_round_trip() {
+        let s = serialize(&Message::DrawOffer).expect("serialize draw offer");
+        assert_eq!(s.len(), 128);
+        assert!(s.starts_with("ChessDRAW:"));
+        assert_eq!(parse(&s), Ok(Message::DrawOffer));
+        assert_eq!(parse("ChessDRAW:"), Err(ParseError::WrongLength));
+    }
+
+    #[test]
+    fn resign_round_trip() {
+        let s = serialize(&Message::Resign).expect("serialize resign");
+        assert_eq!(s.len(), 128);
+        assert!(s.starts_with("ChessRESIGN:"));
+        assert_eq!(parse(&s), Ok(Message::Resign));
+        assert_eq!(parse(&pad("ChessRESIGN:now:")), Err(ParseError::WrongAmountOfFields));
+    }
+
+    #[test]
+    fn rematch_messages_round_trip() {
+        for msg in [Message::Rematch, Message::RematchResponse(true), Message::RematchResponse(false)] {
+            let s = serialize(&msg).expect("serialize rematch");
+            assert_eq!(s.len(), 128);
+            assert_eq!(parse(&s), Ok(msg));
+        }
+
+        assert!(serialize(&Message::Rematch).unwrap().starts_with("ChessREMATCH:"));
+        assert!(serialize(&Message::RematchResponse(false)).unwrap().starts_with("ChessREMATCHREPLY:0:"));
+        assert_eq!(parse(&pad("ChessREMATCHREPLY:maybe:")), Err(ParseError::InvalidRematch));
+        assert_eq!(parse(&pad("ChessREMATCH:now:")), Err(ParseError::WrongAmountOfFields));
+        assert_eq!(parse("ChessREMATCH:"), Err(ParseError::WrongLength));
+    }
+
+    #[test]
+    fn resync_round_trip() {
+        let board = parse_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR").expect("valid FEN");
+        let msg = Message::Resync(MessageResync { board, turn: Color::White, halfmoves: 2 });
+        let s = serialize(&msg).expect("serialize resync");
+        assert_eq!(s.len(), 128);
+        assert!(s.starts_with("ChessRESYNC:rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR:w:2:"), "{s}");
+        assert_eq!(parse(&s), Ok(msg));
+
+        let empty = Message::Resync(MessageResync { board: Board::new_empty(), turn: Color::Black, halfmoves: 301 });
+        assert_eq!(parse(&serialize(&empty).expect("serialize resync")), Ok(empty));
+    }
+
+    #[test]
+    fn parse_invalid_resync() {
+        assert_eq!(parse(&pad("ChessRESYNC:8/8/8/8/8/8/8/8:x:0:")), Err(ParseError::InvalidResync));
+        assert_eq!(parse(&pad("ChessRESYNC:8/8/8/8/8/8/8/8:w:-1:")), Err(ParseError::InvalidResync));
+        assert_eq!(parse(&pad("ChessRESYNC:8/8/8:w:0:")), Err(ParseError::InvalidFENLength));
+        assert_eq!(parse(&pad("ChessRESYNC:8/8/8/8/8/8/8/8:w:")), Err(ParseError::WrongAmountOfFields));
+        assert_eq!(parse("ChessRESYNC:8/8/8/8/8/8/8/8:w:0:"), Err(ParseError::WrongLength));
+    }
+
+    #[test]
+    fn full_fen_in_a_board_field_is_rejected() {
+        let board = parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").expect("valid FEN");
+        let placement = serialize_board(&board);
+        let full_fen = format!("{placement} b KQkq e3 0 1");
+        assert_eq!(parse_fen(&full_fen), Err(ParseError::ExtraFENFields));
+
+        // Swapping the serialized placement for a full FEN inside otherwise valid frames
+        let mv = Message::Move(MessageMove {
+            board: board.clone(),
+            mv: (Position::parse("e2").unwrap(), Position::parse("e4").unwrap()),
+            prom_piece: None,
+            game_state: GameState::Ongoing,
+            move_type: Some(MoveType::Normal),
+        });
+        let resync = Message::Resync(MessageResync { board, turn: Color::Black, halfmoves: 1 });
+        for msg in [mv, resync] {
+            let frame = serialize(&msg).expect("serialize");
+            let leaked = frame.replacen(&format!(":{placement}:"), &format!(":{full_fen}:"), 1);
+            let leaked = &leaked[..FRAME_LEN];
+            assert_eq!(parse(leaked), Err(ParseError::ExtraFENFields), "{leaked}");
+        }
+    }
+
+    #[test]
+    fn serialized_placement_fits_the_framing() {
+        // No spaces or colons, so it stays a single field that parses back on its own
+        for fen in ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR", "8/8/8/8/8/8/8/8", "r3k2r/8/8/3Pp3/8/8/8/R3K2R"] {
+            let board = parse_fen(fen).expect("valid FEN");
+            let placement = serialize_board(&board);
+            assert!(!placement.contains([' ', ':']), "{placement}");
+            assert_eq!(parse_fen(&placement), Ok(board));
+        }
+        // A colon can't hide in the board field either: it splits the frame into more fields
+        let frame = pad("ChessMOVE:E2E40:0-0:8/8/8/8/8/8/8/8:w:0");
+        assert_eq!(parse(&frame), Err(ParseError::WrongAmountOfFields));
+    }
+
+    #[test]
+    fn parse_invalid_takeback() {
+        assert_eq!(parse(&pad("ChessTAKEBACK:x:")), Err(ParseError::InvalidTakeback));
+        assert_eq!(parse(&pad("ChessTAKEBACKREPLY:yes:")), Err(ParseError::InvalidTakeback));
+        assert_eq!(parse(&pad("ChessTAKEBACK:1")), Err(ParseError::WrongAmountOfFields));
+        assert_eq!(parse("ChessTAKEBACK:1:"), Err(ParseError::WrongLength));
+    }
+
+    // Small xorshift generator, so the fuzz test is repeatable without extra crates
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    // Pad to 128 bytes rather than 128 chars, for frames with multi-byte chars
+    fn pad_bytes(msg: &str) -> String {
+        format!("{msg}{}", "0".repeat(FRAME_LEN.saturating_sub(msg.len())))
+    }
+
+    #[test]
+    fn parse_never_panics_on_random_frames() {
+        // Mostly characters that mean something in a frame, plus a few that don't
+        const ALPHABET: &[char] = &[
+            ':', '/', '-', '0', '1', '2', '7', '8', '9', 'a', 'e', 'h', 'A', 'E', 'H', 'p', 'k', 'q', 'P', 'K', 'Q',
+            'N', 'x', 'é', '\u{FFFD}',
+        ];
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for i in 0..20_000 {
+            let frame = if i % 2 == 0 {
+                // Raw bytes, lossily decoded so `parse` sees arbitrary text
+                let bytes: Vec<u8> = (0..FRAME_LEN).map(|_| next_random(&mut state) as u8).collect();
+                String::from_utf8_lossy(&bytes).into_owned()
+            } else {
+                let prefix = ["ChessMOVE:", "ChessQUIT:", "ChessTAKEBACK:", "ChessTAKEBACKREPLY:", "ChessFLAG:"][i % 5];
+                let len = next_random(&mut state) as usize % FRAME_LEN;
+                let body: String = (0..len)
+                    .map(|_| ALPHABET[next_random(&mut state) as usize % ALPHABET.len()])
+                    .collect();
+                pad_bytes(&format!("{prefix}{body}"))
+            };
+            // Either result is fine, as long as it returns
+            let _ = parse(&frame);
+        }
+    }
+
+    #[test]
+    fn parse_regression_seeds() {
+        // More empty squares than the board has, which used to underflow the square index
+        let frame = pad("ChessMOVE:a2a40:0-0:8/8/8/8/8/8/8/8/9:x");
+        assert_eq!(parse(&frame), Err(ParseError::InvalidFENLength));
+        let frame = pad("ChessMOVE:a2a40:0-0:9/8/8/8/8/8/8/8:x");
+        assert_eq!(parse(&frame), Err(ParseError::InvalidFENRank { rank: 8 }));
+
+        // A five byte move with a multi-byte char, which used to be sliced mid-char
+        let frame = pad_bytes("ChessMOVE:a\u{FFFD}0:0-0:8/8/8/8/8/8/8/8:x");
+        assert_eq!(parse(&frame), Err(ParseError::InvalidMoveFormat));
+    }
+}
@@ -0,0 +1,105 @@
+use rsoderh_chess::{PieceKind, Position};
+
+use puhl_gui::protocol::{self, Message};
+use crate::state::ChessState;
+
+// Play the moves in `input` from the standard starting position. Each line
+// holds one move, either in coordinate notation (`e2e4`, `e7e8q`) or as a
+// MOVE message from the protocol. Blank lines and lines starting with '#'
+// are skipped.
+pub fn replay(input: &str) -> Result<ChessState, String> {
+    let mut state = ChessState::default();
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_no = i + 1;
+        if state.outcome.is_some() {
+            return Err(format!("Line {line_no}: move '{line}' after the game ended"));
+        }
+
+        let (mv, prom) = parse_move_line(line).ok_or_else(|| format!("Line {line_no}: can't parse move '{line}'"))?;
+        state
+            .apply(mv, prom)
+            .map_err(|why| format!("Line {line_no}: illegal move '{line}' ({why})"))?;
+    }
+
+    Ok(state)
+}
+
+// Parse one move in coordinate notation or as a MOVE frame into its squares
+// and promotion piece
+pub fn parse_move_line(line: &str) -> Option<((Position, Position), Option<PieceKind>)> {
+    let (mv, prom_piece) = if line.starts_with("ChessMOVE") {
+        match protocol::parse(line).ok()? {
+            Message::Move(message) => (message.mv, message.prom_piece),
+            _ => return None,
+        }
+    } else {
+        if !line.is_ascii() || !(4..=5).contains(&line.len()) {
+            return None;
+        }
+        let mv = (Position::parse(&line[0..2])?, Position::parse(&line[2..4])?);
+        let prom_piece = match line[4..].to_ascii_lowercase().as_str() {
+            "" => None,
+            "n" => Some(PieceKind::Knight),
+            "b" => Some(PieceKind::Bishop),
+            "r" => Some(PieceKind::Rook),
+            "q" => Some(PieceKind::Queen),
+            _ => return None,
+        };
+        (mv, prom_piece)
+    };
+    Some((mv, prom_piece))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::result_line;
+
+    #[test]
+    fn replay_coordinate_moves() {
+        let input = "# Open game\ne2e4\n\ne7e5\n";
+        let result = replay(input).expect("legal moves");
+        assert_eq!(result.fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w");
+        assert_eq!(result.outcome, None);
+    }
+
+    #[test]
+    fn replay_protocol_messages() {
+        let frame = format!("ChessMOVE:E2E40:0-0:{}:0", "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR");
+        let input = format!("{frame:0<128}\n");
+        let result = replay(&input).expect("legal move");
+        assert_eq!(result.fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b");
+    }
+
+    #[test]
+    fn replay_result_line() {
+        // Knights out and back twice: the starting position comes up a third time
+        let input = "g1f3\ng8f6\nf3g1\nf6g8\ng1f3\ng8f6\nf3g1\nf6g8\n";
+        let result = replay(input).expect("legal moves");
+        let outcome = result.outcome.expect("threefold repetition");
+        assert_eq!(
+            result_line(outcome, &result.fen()),
+            "RESULT=1/2-1/2 REASON=repetition FEN=rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w"
+        );
+    }
+
+    #[test]
+    fn replay_reports_the_bad_line() {
+        let err = replay("e2e4\ne7e5\nhello\n").err().expect("unparsable move");
+        assert!(err.starts_with("Line 3"), "{err}");
+    }
+
+    #[test]
+    fn parse_move_line_promotion() {
+        let (mv, prom) = parse_move_line("a7a8Q").expect("valid promotion");
+        assert_eq!(mv, (Position::parse("a7").unwrap(), Position::parse("a8").unwrap()));
+        assert_eq!(prom, Some(PieceKind::Queen));
+        assert!(parse_move_line("a7a8k").is_none());
+        assert!(parse_move_line("a7").is_none());
+    }
+}
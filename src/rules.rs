@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use puhl_gui::protocol::GameState;
+use rsoderh_chess::{Board, Color, Piece, PieceKind, Position, Slot};
+
+// Halfmoves without a capture or pawn move before the game is drawn
+const FIFTY_MOVE_LIMIT: u16 = 100;
+
+// How a finished game ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Checkmate(Color),
+    // The other side's clock ran out; holds the winner
+    Timeout(Color),
+    // The other side resigned; holds the winner
+    Resignation(Color),
+    Draw(DrawReason),
+}
+
+impl Outcome {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Outcome::Checkmate(Color::White) => "White wins by checkmate",
+            Outcome::Checkmate(Color::Black) => "Black wins by checkmate",
+            Outcome::Timeout(Color::White) => "White wins on time",
+            Outcome::Timeout(Color::Black) => "Black wins on time",
+            Outcome::Resignation(Color::White) => "Black resigns, White wins",
+            Outcome::Resignation(Color::Black) => "White resigns, Black wins",
+            Outcome::Draw(DrawReason::Repetition) => "Draw by threefold repetition",
+            Outcome::Draw(DrawReason::FiftyMoves) => "Draw by the fifty-move rule",
+            Outcome::Draw(DrawReason::InsufficientMaterial) => "Draw by insufficient material",
+            Outcome::Draw(DrawReason::Agreement) => "Draw by agreement",
+        }
+    }
+
+    // Short name for scripts, e.g. `checkmate` or `fifty-moves`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Outcome::Checkmate(_) => "checkmate",
+            Outcome::Timeout(_) => "timeout",
+            Outcome::Resignation(_) => "resignation",
+            Outcome::Draw(DrawReason::Repetition) => "repetition",
+            Outcome::Draw(DrawReason::FiftyMoves) => "fifty-moves",
+            Outcome::Draw(DrawReason::InsufficientMaterial) => "insufficient-material",
+            Outcome::Draw(DrawReason::Agreement) => "agreement",
+        }
+    }
+}
+
+// One line for scripts once a game is over, e.g. `RESULT=1-0 REASON=checkmate FEN=<fen>`.
+// The FEN comes last since it contains a space.
+pub fn result_line(outcome: Outcome, fen: &str) -> String {
+    format!("RESULT={} REASON={} FEN={fen}", result_tag(Some(outcome)), outcome.reason())
+}
+
+// PGN style result of a game: `1-0`, `0-1`, `1/2-1/2`, or `*` while it is ongoing
+pub fn result_tag(outcome: Option<Outcome>) -> &'static str {
+    match outcome {
+        Some(Outcome::Checkmate(Color::White) | Outcome::Timeout(Color::White) | Outcome::Resignation(Color::White)) => {
+            "1-0"
+        }
+        Some(Outcome::Checkmate(Color::Black) | Outcome::Timeout(Color::Black) | Outcome::Resignation(Color::Black)) => {
+            "0-1"
+        }
+        Some(Outcome::Draw(_)) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+// Game state field of a MOVE message sent after a move with this outcome
+pub fn game_state(outcome: Option<Outcome>) -> GameState {
+    match outcome {
+        Some(Outcome::Checkmate(Color::White) | Outcome::Timeout(Color::White) | Outcome::Resignation(Color::White)) => {
+            GameState::WinWhite
+        }
+        Some(Outcome::Checkmate(Color::Black) | Outcome::Timeout(Color::Black) | Outcome::Resignation(Color::Black)) => {
+            GameState::WinBlack
+        }
+        Some(Outcome::Draw(_)) => GameState::Draw,
+        None => GameState::Ongoing,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrawReason {
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+    // One side offered a draw and the other accepted
+    Agreement,
+}
+
+// Positions seen so far and the halfmove clock, for the draw rules that
+// rsoderh_chess doesn't track itself
+#[derive(Clone, Debug, Default)]
+pub struct DrawTracker {
+    position_counts: HashMap<u64, u8>,
+    halfmove_clock: u16,
+}
+
+impl DrawTracker {
+    // Start tracking from the initial position of a game
+    pub fn new(board: &Board, turn: Color) -> Self {
+        let mut tracker = Self::default();
+        tracker.position_counts.insert(position_hash(board, turn), 1);
+        tracker
+    }
+
+    // Record the position reached after a move. `irreversible` is set for
+    // captures and pawn moves, which restart the fifty-move count.
+    pub fn record(&mut self, board: &Board, turn: Color, irreversible: bool) -> Option<DrawReason> {
+        if irreversible {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+
+        let count = self.position_counts.entry(position_hash(board, turn)).or_insert(0);
+        *count += 1;
+
+        if *count >= 3 {
+            Some(DrawReason::Repetition)
+        } else if self.halfmove_clock >= FIFTY_MOVE_LIMIT {
+            Some(DrawReason::FiftyMoves)
+        } else {
+            None
+        }
+    }
+}
+
+// Whether neither side can possibly checkmate: K vs K, K+B vs K, K+N vs K, or
+// K+B vs K+B with both bishops on squares of the same color
+pub fn insufficient_material(board: &Board) -> bool {
+    // Minor pieces besides the kings, with the square color of each bishop
+    let mut minors = Vec::new();
+    for row in 0..8 {
+        for column in 0..8 {
+            let Slot::Occupied(piece) = board.at_position(Position::new(column, row).unwrap()) else { continue };
+            match piece.kind {
+                PieceKind::King => {}
+                PieceKind::Bishop => minors.push((piece.color, Some((column + row) % 2))),
+                PieceKind::Knight => minors.push((piece.color, None)),
+                PieceKind::Pawn | PieceKind::Rook | PieceKind::Queen => return false,
+            }
+        }
+    }
+
+    match minors[..] {
+        [] | [_] => true,
+        [(first, Some(first_square)), (second, Some(second_square))] => first != second && first_square == second_square,
+        _ => false,
+    }
+}
+
+// Pieces of either color that are absolutely pinned: the only piece between
+// their own king and an enemy rook, bishop or queen that moves along that line.
+// They can still move along the line, just not off it.
+pub fn pinned_pieces(board: &Board) -> Vec<Position> {
+    let mut pinned = Vec::new();
+    for row in 0..8 {
+        for column in 0..8 {
+            let king = Position::new(column, row).unwrap();
+            let Slot::Occupied(Piece { kind: PieceKind::King, color }) = board.at_position(king) else { continue };
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)] {
+                let diagonal = dx != 0 && dy != 0;
+                let mut shield = None;
+                let (mut x, mut y) = (column as i8, row as i8);
+                loop {
+                    (x, y) = (x + dx, y + dy);
+                    if !(0..8).contains(&x) || !(0..8).contains(&y) {
+                        break;
+                    }
+                    let position = Position::new(x as u8, y as u8).unwrap();
+                    let Slot::Occupied(piece) = board.at_position(position) else { continue };
+                    match shield {
+                        None if piece.color == color => shield = Some(position),
+                        None => break,
+                        Some(shield) => {
+                            let slides_here = match piece.kind {
+                                PieceKind::Queen => true,
+                                PieceKind::Bishop => diagonal,
+                                PieceKind::Rook => !diagonal,
+                                _ => false,
+                            };
+                            if piece.color != color && slides_here {
+                                pinned.push(shield);
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    pinned
+}
+
+// Hash of the piece placement and side to move
+pub fn position_hash(board: &Board, turn: Color) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for row in 0..8 {
+        for column in 0..8 {
+            match board.at_position(Position::new(column, row).unwrap()) {
+                Slot::Occupied(piece) => Some(piece),
+                Slot::Empty => None,
+            }
+            .hash(&mut hasher);
+        }
+    }
+    (turn == Color::White).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kings_and(pieces: &[(&str, Color, PieceKind)]) -> Board {
+        let mut board = Board::new_empty();
+        *board.at_position_mut(Position::parse("e1").unwrap()) =
+            Slot::Occupied(Piece { color: Color::White, kind: PieceKind::King });
+        *board.at_position_mut(Position::parse("e8").unwrap()) =
+            Slot::Occupied(Piece { color: Color::Black, kind: PieceKind::King });
+        for &(square, color, kind) in pieces {
+            *board.at_position_mut(Position::parse(square).unwrap()) = Slot::Occupied(Piece { color, kind });
+        }
+        board
+    }
+
+    #[test]
+    fn threefold_repetition_is_a_draw() {
+        let knight = |square| kings_and(&[(square, Color::White, PieceKind::Knight)]);
+        let mut tracker = DrawTracker::default();
+        assert_eq!(tracker.record(&knight("g1"), Color::White, false), None);
+
+        // The knight hops out and back while the opponent passes; only the
+        // resulting positions matter to the tracker
+        let shuffle = [("f3", Color::Black), ("f3", Color::White), ("g1", Color::Black), ("g1", Color::White)];
+        for (square, turn) in shuffle {
+            assert_eq!(tracker.record(&knight(square), turn, false), None);
+        }
+        for (square, turn) in &shuffle[..3] {
+            assert_eq!(tracker.record(&knight(square), *turn, false), None);
+        }
+        assert_eq!(tracker.record(&knight("g1"), Color::White, false), Some(DrawReason::Repetition));
+    }
+
+    #[test]
+    fn side_to_move_is_part_of_the_position() {
+        let board = kings_and(&[("g1", Color::White, PieceKind::Knight)]);
+        assert_ne!(position_hash(&board, Color::White), position_hash(&board, Color::Black));
+
+        let mut tracker = DrawTracker::default();
+        for turn in [Color::White, Color::Black, Color::White, Color::Black] {
+            assert_eq!(tracker.record(&board, turn, false), None);
+        }
+    }
+
+    #[test]
+    fn fifty_moves_without_progress_is_a_draw() {
+        let mut tracker = DrawTracker::default();
+        // Fifty distinct knight squares, each visited once per side to move
+        let squares = (0..64)
+            .map(|i| Position::new(i % 8, i / 8).unwrap())
+            .filter(|pos| ![Position::parse("e1"), Position::parse("e8")].contains(&Some(*pos)))
+            .take(50);
+
+        let mut draws = Vec::new();
+        for pos in squares {
+            let square = format!("{}{}", (b'a' + pos.column()) as char, pos.row() + 1);
+            let board = kings_and(&[(&square, Color::White, PieceKind::Knight)]);
+            for turn in [Color::Black, Color::White] {
+                draws.push(tracker.record(&board, turn, false));
+            }
+        }
+        assert!(draws[..99].iter().all(Option::is_none));
+        assert_eq!(draws[99], Some(DrawReason::FiftyMoves));
+    }
+
+    #[test]
+    fn capture_resets_the_fifty_move_clock() {
+        let knight = |square| kings_and(&[(square, Color::White, PieceKind::Knight)]);
+        let mut tracker = DrawTracker { halfmove_clock: 98, ..Default::default() };
+        assert_eq!(tracker.record(&knight("f3"), Color::Black, true), None);
+        assert_eq!(tracker.record(&knight("g5"), Color::White, false), None);
+    }
+
+    #[test]
+    fn insufficient_material_combinations() {
+        use Color::{Black, White};
+        use PieceKind::{Bishop, Knight};
+
+        assert!(insufficient_material(&kings_and(&[])), "K vs K");
+        assert!(insufficient_material(&kings_and(&[("c1", White, Bishop)])), "K+B vs K");
+        assert!(insufficient_material(&kings_and(&[("g8", Black, Knight)])), "K+N vs K");
+        // c1 and f8 are both dark squares
+        assert!(
+            insufficient_material(&kings_and(&[("c1", White, Bishop), ("f8", Black, Bishop)])),
+            "K+B vs K+B, same colored bishops"
+        );
+    }
+
+    #[test]
+    fn pieces_between_king_and_slider_are_pinned() {
+        let board = kings_and(&[
+            // Pinned on the file, the diagonal and the rank
+            ("e2", Color::White, PieceKind::Rook),
+            ("e5", Color::Black, PieceKind::Rook),
+            // Keeps the white rook from pinning the black rook in turn
+            ("e7", Color::Black, PieceKind::Pawn),
+            ("d2", Color::White, PieceKind::Knight),
+            ("b4", Color::Black, PieceKind::Bishop),
+            ("f8", Color::Black, PieceKind::Pawn),
+            ("h8", Color::White, PieceKind::Queen),
+        ]);
+        let mut pinned = pinned_pieces(&board);
+        pinned.sort_by_key(|p| (p.row(), p.column()));
+        let expected: Vec<_> = ["d2", "e2", "f8"].iter().map(|s| Position::parse(s).unwrap()).collect();
+        assert_eq!(pinned, expected);
+    }
+
+    #[test]
+    fn unpinned_lines() {
+        let board = kings_and(&[
+            // A bishop doesn't pin along a file, nor a rook along a diagonal
+            ("e2", Color::White, PieceKind::Knight),
+            ("e4", Color::Black, PieceKind::Bishop),
+            ("f2", Color::White, PieceKind::Pawn),
+            ("h4", Color::Black, PieceKind::Rook),
+            // Two pieces in the way, and an attacker of our own color
+            ("d7", Color::Black, PieceKind::Pawn),
+            ("c6", Color::Black, PieceKind::Pawn),
+            ("a4", Color::White, PieceKind::Bishop),
+            ("f7", Color::Black, PieceKind::Knight),
+            ("h5", Color::Black, PieceKind::Queen),
+        ]);
+        assert_eq!(pinned_pieces(&board), Vec::new());
+    }
+
+    #[test]
+    fn sufficient_material() {
+        use Color::{Black, White};
+        use PieceKind::{Bishop, Knight, Pawn, Rook};
+
+        assert!(!insufficient_material(&kings_and(&[("b1", White, Knight), ("g1", White, Knight)])), "K+2N vs K");
+        // c1 is dark, c8 is light
+        assert!(!insufficient_material(&kings_and(&[("c1", White, Bishop), ("c8", Black, Bishop)])));
+        assert!(!insufficient_material(&kings_and(&[("c1", White, Bishop), ("f1", White, Bishop)])));
+        assert!(!insufficient_material(&kings_and(&[("a2", White, Pawn)])));
+        assert!(!insufficient_material(&kings_and(&[("a8", Black, Rook)])));
+        assert!(!insufficient_material(&kings_and(&[("c1", White, Bishop), ("g8", Black, Knight)])));
+    }
+
+    #[test]
+    fn mating_move_is_sent_with_the_winner() {
+        use puhl_gui::protocol::{serialize, Message, MessageMove};
+        use Color::{Black, White};
+        use PieceKind::Queen;
+
+        let message = Message::Move(MessageMove {
+            board: kings_and(&[("h7", White, Queen)]),
+            mv: (Position::parse("h1").unwrap(), Position::parse("h7").unwrap()),
+            prom_piece: None,
+            game_state: game_state(Some(Outcome::Checkmate(White))),
+            move_type: None,
+        });
+        let frame = serialize(&message).expect("serialize move");
+        assert_eq!(frame.split(':').nth(2), Some("1-0"));
+
+        assert_eq!(game_state(Some(Outcome::Checkmate(Black))), GameState::WinBlack);
+        assert_eq!(game_state(Some(Outcome::Draw(DrawReason::Repetition))), GameState::Draw);
+        assert_eq!(game_state(None), GameState::Ongoing);
+    }
+}
@@ -0,0 +1,196 @@
+use rsoderh_chess::Color;
+
+use puhl_gui::protocol::parse_fen;
+use crate::replay::parse_move_line;
+use crate::rules::{result_tag, DrawReason, Outcome};
+use crate::state::{check_kings, game_from, ChessState};
+
+// Saved game in the working directory, written by `S` and read by `L`
+pub const SAVE_FILE: &str = "chess.save";
+
+const HEADER: &str = "# puhl-gui save";
+
+// Save file contents for `state`: the position as FEN, the fullmove number, the
+// result and, once the game is over, why it ended, and the moves in coordinate
+// notation, one field per line
+pub fn save_game(state: &ChessState) -> String {
+    let moves: Vec<&str> = state.moves().collect();
+    let reason = state.outcome.map(|outcome| format!("reason {}\n", outcome.reason())).unwrap_or_default();
+    format!(
+        "{HEADER}\nfen {}\nfullmove {}\nresult {}\n{reason}moves {}\n",
+        state.fen(),
+        state.fullmove(),
+        result_tag(state.outcome),
+        moves.join(" ")
+    )
+}
+
+// Rebuild a game from a save file. The moves are replayed from the standard
+// starting position so that takebacks and draw tracking keep working, and the
+// replayed position must match the saved FEN, fullmove number, result and
+// reason; a result the position doesn't explain is rebuilt from the reason. A
+// save without moves is loaded straight from its FEN, counting moves from its
+// fullmove number. Saves from before the fullmove field count from 1.
+pub fn load_game(contents: &str) -> Result<ChessState, String> {
+    let mut fen = None;
+    let mut fullmove = None;
+    let mut result = None;
+    let mut reason = None;
+    let mut moves = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "fen" => fen = Some(value.trim()),
+            "fullmove" => {
+                let number = value.trim().parse().ok().filter(|&n: &u32| n > 0);
+                fullmove = Some(number.ok_or_else(|| format!("Invalid fullmove number '{value}'"))?);
+            }
+            "result" => result = Some(value.trim()),
+            "reason" => reason = Some(value.trim()),
+            "moves" => moves = Some(value.split_whitespace().collect::<Vec<_>>()),
+            _ => return Err(format!("Unknown field '{key}'")),
+        }
+    }
+    let fen = fen.ok_or("Missing fen")?;
+    let result = result.ok_or("Missing result")?;
+
+    let state = match moves {
+        Some(moves) if !moves.is_empty() => {
+            let mut state = ChessState::default();
+            for mv in moves {
+                let (squares, prom) = parse_move_line(mv).ok_or_else(|| format!("Can't parse move '{mv}'"))?;
+                state.apply(squares, prom).map_err(|why| format!("Illegal move '{mv}' ({why})"))?;
+            }
+            if state.fen() != fen || fullmove.is_some_and(|n| n != state.fullmove()) {
+                return Err("Moves don't lead to the saved position".to_string());
+            }
+            state
+        }
+        _ => {
+            let (placement, turn) = fen.split_once(' ').ok_or("Missing side to move")?;
+            let turn = match turn {
+                "w" => Color::White,
+                "b" => Color::Black,
+                _ => return Err(format!("Invalid side to move '{turn}'")),
+            };
+            let board = parse_fen(placement).map_err(|err| format!("Invalid fen ({err:?})"))?;
+            check_kings(&board).map_err(|why| format!("Invalid fen ({why})"))?;
+            ChessState::new(game_from(board, turn)).with_fullmove(fullmove.unwrap_or(1))
+        }
+    };
+
+    let mut state = state;
+    if state.outcome.is_none() {
+        state.outcome = unexplained_outcome(result, reason);
+    }
+    if result_tag(state.outcome) != result {
+        return Err(format!("Result '{result}' doesn't match the position"));
+    }
+    if let Some(reason) = reason
+        && state.outcome.is_none_or(|outcome| outcome.reason() != reason)
+    {
+        return Err(format!("Reason '{reason}' doesn't match the position"));
+    }
+    Ok(state)
+}
+
+// How a game ended that its position doesn't explain, from the saved result and
+// reason. Saves from before the reason field only had decisive results lost on time.
+fn unexplained_outcome(result: &str, reason: Option<&str>) -> Option<Outcome> {
+    let winner = match result {
+        "1-0" => Color::White,
+        "0-1" => Color::Black,
+        "1/2-1/2" if reason == Some("agreement") => return Some(Outcome::Draw(DrawReason::Agreement)),
+        _ => return None,
+    };
+    match reason {
+        Some("resignation") => Some(Outcome::Resignation(winner)),
+        Some("timeout") | None => Some(Outcome::Timeout(winner)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::play;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut state = ChessState::default();
+        play(&mut state, &[("e2", "e4"), ("e7", "e5"), ("g1", "f3")]);
+
+        let saved = save_game(&state);
+        assert!(saved.contains("moves e2e4 e7e5 g1f3"), "{saved}");
+        assert!(saved.contains("fullmove 2"), "{saved}");
+        assert!(saved.contains("result *"), "{saved}");
+
+        let loaded = load_game(&saved).expect("valid save");
+        assert_eq!(loaded.fen(), state.fen());
+        assert_eq!(loaded.halfmoves(), 3);
+        assert_eq!(loaded.fullmove(), 2);
+        assert_eq!(loaded.outcome, None);
+    }
+
+    #[test]
+    fn load_position_without_moves() {
+        let contents = "fen 4k3/8/8/8/8/8/4P3/4K3 b\nfullmove 31\nresult *\nmoves\n";
+        let loaded = load_game(contents).expect("valid save");
+        assert_eq!(loaded.fen(), "4k3/8/8/8/8/8/4P3/4K3 b");
+        assert_eq!(loaded.turn(), Color::Black);
+        assert_eq!(loaded.halfmoves(), 0);
+        assert_eq!(loaded.fullmove(), 31);
+    }
+
+    #[test]
+    fn decisive_result_without_mate_is_a_timeout() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+        let loaded = load_game(&format!("fen {start} w\nresult 0-1\n")).expect("valid save");
+        assert_eq!(loaded.outcome, Some(Outcome::Timeout(Color::Black)));
+    }
+
+    #[test]
+    fn agreed_draw_round_trip() {
+        let mut state = ChessState::default();
+        play(&mut state, &[("e2", "e4"), ("e7", "e5")]);
+        state.outcome = Some(Outcome::Draw(DrawReason::Agreement));
+
+        let saved = save_game(&state);
+        assert!(saved.contains("result 1/2-1/2\nreason agreement\n"), "{saved}");
+        let loaded = load_game(&saved).expect("valid save");
+        assert_eq!(loaded.outcome, Some(Outcome::Draw(DrawReason::Agreement)));
+        assert_eq!(loaded.fen(), state.fen());
+    }
+
+    #[test]
+    fn resignation_round_trip() {
+        let mut state = ChessState::default();
+        play(&mut state, &[("e2", "e4")]);
+        state.outcome = Some(Outcome::Resignation(Color::White));
+
+        let saved = save_game(&state);
+        assert!(saved.contains("result 1-0\nreason resignation\n"), "{saved}");
+        let loaded = load_game(&saved).expect("valid save");
+        assert_eq!(loaded.outcome, Some(Outcome::Resignation(Color::White)));
+    }
+
+    #[test]
+    fn corrupt_saves_are_rejected() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+        assert!(load_game("").is_err(), "empty file");
+        assert!(load_game(&format!("fen {start} w\n")).is_err(), "missing result");
+        assert!(load_game(&format!("fen {start} x\nresult *\n")).is_err(), "bad side to move");
+        assert!(load_game(&format!("fen {start} w\nresult *\nmoves e2e4 hello\n")).is_err(), "bad move");
+        assert!(load_game(&format!("fen {start} w\nresult *\nmoves e2e4\n")).is_err(), "position mismatch");
+        assert!(load_game(&format!("fen {start} w\nresult 1/2-1/2\n")).is_err(), "result mismatch");
+        assert!(load_game(&format!("fen {start} w\nresult 1-0\nreason checkmate\n")).is_err(), "reason mismatch");
+        assert!(load_game(&format!("fen {start} w\nfullmove 0\nresult *\n")).is_err(), "bad fullmove");
+        assert!(load_game("fen 8/8/8/8/8/8/8/4K3 w\nresult *\n").is_err(), "no black king");
+        let after_e4 = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b";
+        assert!(load_game(&format!("fen {after_e4}\nfullmove 3\nresult *\nmoves e2e4\n")).is_err(), "fullmove mismatch");
+    }
+}
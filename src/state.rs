@@ -0,0 +1,1004 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+use rsoderh_chess::{Board, CheckOutcome, Color, Game, HalfMoveRequest, MoveResult, Piece, PieceKind, Position, Slot};
+
+use crate::notation::{describe_move, piece_letter, Check};
+use puhl_gui::protocol::{board_diff, classify_move, serialize_board, MessageMove, MoveType};
+use crate::rules::{insufficient_material, pinned_pieces, result_tag, DrawReason, DrawTracker, Outcome};
+
+// What a successfully applied move did
+#[derive(Clone, Debug, PartialEq)]
+pub struct Applied {
+    // Long algebraic notation of the move
+    pub notation: String,
+    // Castling and en passant are told apart here, for animating the rook
+    pub move_type: MoveType,
+    pub capture: bool,
+    pub check: bool,
+    // Set if the move ended the game
+    pub outcome: Option<Outcome>,
+    // Set if the move declined the opponent's draw offer
+    pub declined_draw: bool,
+}
+
+// Why a move received from the network opponent was refused
+#[derive(Clone, Debug, PartialEq)]
+pub enum RemoteMoveError {
+    // Not theirs to make: out of turn, or not their piece
+    ProtocolViolation(String),
+    // The move doesn't fit our position, so the two boards no longer agree
+    Desync(String),
+}
+
+// What a left-click on the board does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Click {
+    Select(Position),
+    // Move the selected piece; a pawn reaching the far rank still needs its piece
+    Move(Position, Position),
+    // The selected piece can't go there, so flash it
+    Reject(Position),
+    Deselect,
+}
+
+// Everything needed to step back to before a move, and the move itself in
+// coordinate and long algebraic notation
+struct Snapshot {
+    mv: String,
+    notation: String,
+    board: Board,
+    turn: Color,
+    outcome: Option<Outcome>,
+    draws: DrawTracker,
+    fullmove: u32,
+    en_passant: Option<Position>,
+    // When the move was made, for the time each side spent on it
+    made_at: Instant,
+}
+
+// The game and everything derived from its moves, without any rendering
+pub struct ChessState {
+    pub game: Game,
+    pub outcome: Option<Outcome>,
+    draws: DrawTracker,
+    history: Vec<Snapshot>,
+    // Fullmove number as in FEN: starts at 1 and goes up after each Black move
+    fullmove: u32,
+    // Legal destinations of the last piece asked about, so the selected piece's
+    // moves are worked out once rather than every frame. Cleared when the board
+    // changes.
+    cached_moves: Option<(Position, Vec<Position>)>,
+    // Pinned pieces of the current position, worked out only when asked for
+    cached_pins: Option<Vec<Position>>,
+    // Side with an outstanding draw offer. The other side declines it by moving.
+    pub draw_offer: Option<Color>,
+    // Square a pawn skipped with a double step on the last move, where it can
+    // be captured en passant
+    en_passant: Option<Position>,
+    // When the game was set up, so the first move's thinking time can be told
+    started: Instant,
+}
+
+impl Default for ChessState {
+    fn default() -> Self {
+        Self::new(Game::new_standard())
+    }
+}
+
+impl ChessState {
+    pub fn new(game: Game) -> Self {
+        let draws = DrawTracker::new(game.board(), game.turn);
+        Self {
+            game,
+            outcome: None,
+            draws,
+            history: Vec::new(),
+            fullmove: 1,
+            cached_moves: None,
+            cached_pins: None,
+            draw_offer: None,
+            en_passant: None,
+            started: Instant::now(),
+        }
+    }
+
+    // Start counting moves from `fullmove`, e.g. the fullmove field of a FEN
+    pub fn with_fullmove(self, fullmove: u32) -> Self {
+        Self { fullmove, ..self }
+    }
+
+    pub fn board(&self) -> &Board {
+        self.game.board()
+    }
+
+    pub fn turn(&self) -> Color {
+        self.game.turn
+    }
+
+    // Piece placement and side to move, e.g. `rnbqkbnr/.../RNBQKBNR w`
+    pub fn fen(&self) -> String {
+        let turn = if self.turn() == Color::White { "w" } else { "b" };
+        format!("{} {}", serialize_board(self.board()), turn)
+    }
+
+    // Moves played so far in coordinate notation, e.g. `e2e4` or `e7e8q`
+    pub fn moves(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(|snapshot| snapshot.mv.as_str())
+    }
+
+    pub fn fullmove(&self) -> u32 {
+        self.fullmove
+    }
+
+    // Moves played so far in long algebraic notation, each with the fullmove
+    // number and side that played it
+    pub fn notations(&self) -> impl Iterator<Item = (u32, Color, &str)> {
+        self.history.iter().map(|snapshot| (snapshot.fullmove, snapshot.turn, snapshot.notation.as_str()))
+    }
+
+    // The moves as numbered movetext tokens, e.g. `1. e2-e4`, `e7-e5`; a game
+    // starting with Black to move opens with `1... e7-e5`
+    pub fn movetext(&self) -> Vec<String> {
+        self.notations()
+            .enumerate()
+            .map(|(i, (number, color, notation))| match color {
+                Color::White => format!("{number}. {notation}"),
+                Color::Black if i == 0 => format!("{number}... {notation}"),
+                Color::Black => notation.to_string(),
+            })
+            .collect()
+    }
+
+    // Move history for display, ending with the result once the game is over:
+    // `1-0`, `0-1` or `½-½`
+    pub fn history(&self) -> String {
+        let mut history = self.movetext();
+        match self.outcome {
+            Some(Outcome::Draw(_)) => history.push("½-½".to_string()),
+            Some(outcome) => history.push(result_tag(Some(outcome)).to_string()),
+            None => {}
+        }
+        history.join(" ")
+    }
+
+    // When each move was made, in order
+    pub fn move_times(&self) -> impl Iterator<Item = Instant> {
+        self.history.iter().map(|snapshot| snapshot.made_at)
+    }
+
+    // How long the side to move took over each move, from the position
+    // arising to the move being made
+    pub fn thinking_times(&self) -> Vec<Duration> {
+        let mut previous = self.started;
+        self.move_times()
+            .map(|made_at| {
+                let thinking = made_at.saturating_duration_since(previous);
+                previous = made_at;
+                thinking
+            })
+            .collect()
+    }
+
+    // Position the game started from: the board, side to move and fullmove number
+    pub fn start(&self) -> (&Board, Color, u32) {
+        match self.history.first() {
+            Some(snapshot) => (&snapshot.board, snapshot.turn, snapshot.fullmove),
+            None => (self.board(), self.turn(), self.fullmove),
+        }
+    }
+
+    // Board after the first `ply` halfmoves, from the start (0) up to the
+    // current position (`halfmoves()`)
+    pub fn board_at(&self, ply: usize) -> Option<&Board> {
+        match self.history.get(ply) {
+            Some(snapshot) => Some(&snapshot.board),
+            None if ply == self.history.len() => Some(self.board()),
+            None => None,
+        }
+    }
+
+    // Number of halfmoves played, which is also how many can be taken back
+    pub fn halfmoves(&self) -> usize {
+        self.history.len()
+    }
+
+    // Apply the move from `mv.0` to `mv.1`, promoting to `prom` if given,
+    // updating the outcome and draw tracking. An illegal move leaves the state
+    // as it was and returns why.
+    pub fn apply(&mut self, mv: (Position, Position), prom: Option<PieceKind>) -> Result<Applied, String> {
+        let mut snapshot = Snapshot {
+            mv: String::new(),
+            notation: String::new(),
+            board: self.game.board().clone(),
+            turn: self.game.turn,
+            outcome: self.outcome,
+            draws: self.draws.clone(),
+            fullmove: self.fullmove,
+            en_passant: self.en_passant,
+            made_at: Instant::now(),
+        };
+        self.cached_moves = None;
+        self.cached_pins = None;
+        let placeholder = game_from(self.game.board().clone(), self.game.turn);
+        let current = mem::replace(&mut self.game, placeholder);
+        let turn = current.turn;
+
+        let capture = matches!(current.board().at_position(mv.1), Slot::Occupied(_));
+        let pawn = matches!(current.board().at_position(mv.0), Slot::Occupied(Piece { kind: PieceKind::Pawn, .. }));
+        let irreversible = capture || pawn;
+        let request = match prom {
+            Some(kind) => HalfMoveRequest::Promotion { column: mv.1.column, kind },
+            None => HalfMoveRequest::Standard { source: mv.0, dest: mv.1 },
+        };
+        snapshot.mv = format!("{}{}", square_name(mv.0), square_name(mv.1));
+        if let Some(kind) = prom {
+            snapshot.mv += &piece_letter(kind).to_ascii_lowercase();
+        }
+        let before = current.board().clone();
+        let move_type = classify_move(&before, mv);
+        let notation = |check| describe_move(&before, mv, prom, check);
+
+        let mut applied = match current.perform_move(request) {
+            MoveResult::Ongoing(new_game, check) => {
+                let draw = self.draws.record(new_game.board(), new_game.turn, irreversible);
+                let draw = draw.or_else(|| {
+                    insufficient_material(new_game.board()).then_some(DrawReason::InsufficientMaterial)
+                });
+                let outcome = draw.map(Outcome::Draw);
+                self.game = new_game;
+                Applied {
+                    notation: notation(Check::from(&check)),
+                    move_type,
+                    capture,
+                    check: matches!(check, CheckOutcome::Check),
+                    outcome,
+                    declined_draw: false,
+                }
+            }
+            MoveResult::Finished(finished) => {
+                let rsoderh_chess::GameResult::Checkmate { winner, .. } = finished.result();
+                let outcome = Some(Outcome::Checkmate(*winner));
+                self.game = game_from(finished.board().clone(), turn);
+                Applied { notation: notation(Check::Checkmate), move_type, capture, check: true, outcome, declined_draw: false }
+            }
+            MoveResult::Illegal(previous, why) => {
+                self.game = previous;
+                return Err(format!("{why:?}"));
+            }
+        };
+
+        if applied.outcome.is_some() {
+            self.outcome = applied.outcome;
+        }
+        // Offering a draw and then moving keeps the offer open for the opponent
+        if self.draw_offer.is_some_and(|offered_by| offered_by != turn) {
+            self.draw_offer = None;
+            applied.declined_draw = true;
+        }
+        if turn == Color::Black {
+            self.fullmove += 1;
+        }
+        self.en_passant = double_step(&before, mv);
+        snapshot.notation = applied.notation.clone();
+        self.history.push(snapshot);
+        Ok(applied)
+    }
+
+    // Take back the last move. Returns false if no move has been played.
+    // The game is rebuilt from the board and side to move, like the
+    // placeholder in `apply`.
+    pub fn undo(&mut self) -> bool {
+        let Some(snapshot) = self.history.pop() else { return false };
+        self.cached_moves = None;
+        self.cached_pins = None;
+        self.draw_offer = None;
+        self.game = game_from(snapshot.board, snapshot.turn);
+        self.outcome = snapshot.outcome;
+        self.draws = snapshot.draws;
+        self.fullmove = snapshot.fullmove;
+        self.en_passant = snapshot.en_passant;
+        true
+    }
+
+    // Legal destinations of the piece on `src`, worked out on the first call
+    // for that square and cached until the board changes
+    pub fn legal_moves(&mut self, src: Position) -> &[Position] {
+        if self.cached_moves.as_ref().is_none_or(|(cached, _)| *cached != src) {
+            let moves = self.game.valid_moves(src).into_iter().flatten().collect();
+            self.cached_moves = Some((src, moves));
+        }
+        &self.cached_moves.as_ref().unwrap().1
+    }
+
+    // Moves cached by `legal_moves` for `src`, for drawing without `&mut self`
+    pub fn cached_moves(&self, src: Position) -> Option<&[Position]> {
+        match &self.cached_moves {
+            Some((cached, moves)) if *cached == src => Some(moves),
+            _ => None,
+        }
+    }
+
+    // En passant target square, if the last move was a pawn's double step
+    pub fn en_passant(&self) -> Option<Position> {
+        self.en_passant
+    }
+
+    // Pieces pinned to their king, worked out once per position
+    pub fn pinned(&mut self) -> &[Position] {
+        self.cached_pins.get_or_insert_with(|| pinned_pieces(self.game.board()))
+    }
+
+    // Pins cached by `pinned`, for drawing without `&mut self`
+    pub fn cached_pins(&self) -> Option<&[Position]> {
+        self.cached_pins.as_deref()
+    }
+
+    // `by` offers a draw, which agrees to the other side's outstanding offer.
+    // Returns whether the game is now drawn.
+    pub fn offer_draw(&mut self, by: Color) -> bool {
+        if self.outcome.is_some() {
+            return false;
+        }
+        match self.draw_offer {
+            Some(offered_by) if offered_by != by => {
+                self.draw_offer = None;
+                self.outcome = Some(Outcome::Draw(DrawReason::Agreement));
+                true
+            }
+            _ => {
+                self.draw_offer = Some(by);
+                false
+            }
+        }
+    }
+
+    // Whether `position` holds a piece of the side to move
+    pub fn movable(&self, position: Position) -> bool {
+        matches!(self.board().at_position(position), Slot::Occupied(piece) if piece.color == self.turn())
+    }
+
+    // Whether moving the piece on `src` to `dest` promotes it, i.e. a pawn
+    // reaching the far rank. Returns the pawn's color if so.
+    pub fn promotes(&self, src: Position, dest: Position) -> Option<Color> {
+        match self.board().at_position(src) {
+            Slot::Occupied(Piece { color, kind: PieceKind::Pawn }) if dest.row() == promotion_rank(color) => Some(color),
+            _ => None,
+        }
+    }
+
+    // Check that a move from `sender` starting on `src` is theirs to make: it
+    // has to be their turn and their piece
+    pub fn check_sender(&self, sender: Color, src: Position) -> Result<(), String> {
+        let side = |color| if color == Color::White { "White" } else { "Black" };
+        if self.turn() != sender {
+            return Err(format!("{} moved on {}'s turn", side(sender), side(self.turn())));
+        }
+        match self.board().at_position(src) {
+            Slot::Occupied(piece) if piece.color == sender => Ok(()),
+            Slot::Occupied(piece) => {
+                Err(format!("{} moved {}'s piece on {}", side(sender), side(piece.color), square_name(src)))
+            }
+            Slot::Empty => Err(format!("{} moved from the empty square {}", side(sender), square_name(src))),
+        }
+    }
+
+    // Apply a move received from `sender`. It has to be theirs to make, legal
+    // here, and leave the board they sent. A refused move leaves the state as it was.
+    pub fn apply_remote(&mut self, sender: Color, message: &MessageMove) -> Result<Applied, RemoteMoveError> {
+        self.check_sender(sender, message.mv.0).map_err(RemoteMoveError::ProtocolViolation)?;
+        let applied = self
+            .apply(message.mv, message.prom_piece)
+            .map_err(|why| RemoteMoveError::Desync(format!("the opponent's move was illegal ({why})")))?;
+        // The opponent's board after the move has to match ours
+        let diff = board_diff(&message.board, self.board());
+        if !diff.is_empty() {
+            self.undo();
+            let squares: Vec<_> = diff.iter().map(|&(position, _, _)| square_name(position)).collect();
+            return Err(RemoteMoveError::Desync(format!("the boards differ on {}", squares.join(", "))));
+        }
+        Ok(applied)
+    }
+
+    // What a left-click on `clicked` does with `selected` selected. Anything
+    // but Select leaves nothing selected, so a square rsoderh_chess has no moves
+    // for (valid_moves returns None) never stays selected.
+    pub fn click(&mut self, selected: Option<Position>, clicked: Position) -> Click {
+        let Some(src) = selected else {
+            if self.movable(clicked) && self.game.valid_moves(clicked).is_some() {
+                self.legal_moves(clicked);
+                return Click::Select(clicked);
+            }
+            return Click::Deselect;
+        };
+        // e.g. the selected square emptied or changed hands since it was selected
+        if self.game.valid_moves(src).is_none() {
+            return Click::Deselect;
+        }
+
+        if self.legal_moves(src).contains(&clicked) {
+            Click::Move(src, clicked)
+        } else if clicked != src && !self.movable(clicked) {
+            Click::Reject(src)
+        } else {
+            // Clicking the piece again or another of ours is just a change of mind
+            Click::Deselect
+        }
+    }
+
+    // JSON dump of the position for debugging move generation: the FEN, side
+    // to move and the legal destinations of each piece of the side to move,
+    // straight from rsoderh_chess and in square order
+    pub fn debug_json(&self) -> String {
+        let mut pieces = Vec::new();
+        for row in 0..8 {
+            for column in 0..8 {
+                let src = Position::new(column, row).unwrap();
+                if !self.movable(src) {
+                    continue;
+                }
+                let dests: Vec<String> = self
+                    .game
+                    .valid_moves(src)
+                    .into_iter()
+                    .flatten()
+                    .map(|dest| format!("\"{}\"", square_name(dest)))
+                    .collect();
+                pieces.push(format!("    \"{}\": [{}]", square_name(src), dests.join(", ")));
+            }
+        }
+        let turn = if self.turn() == Color::White { "white" } else { "black" };
+        format!(
+            "{{\n  \"fen\": \"{}\",\n  \"turn\": \"{turn}\",\n  \"moves\": {{\n{}\n  }}\n}}",
+            self.fen(),
+            pieces.join(",\n")
+        )
+    }
+}
+
+// Squares the rook jumps between when the king castles onto `king_dest`
+pub fn castling_rook(move_type: MoveType, king_dest: Position) -> Option<(Position, Position)> {
+    let (from, to) = match move_type {
+        MoveType::CastleKingside => (7, 5),
+        MoveType::CastleQueenside => (0, 3),
+        MoveType::Normal | MoveType::EnPassant => return None,
+    };
+    let rank = king_dest.row();
+    Some((Position::new(from, rank).unwrap(), Position::new(to, rank).unwrap()))
+}
+
+// Algebraic name of a square, e.g. `e4`
+pub fn square_name(position: Position) -> String {
+    format!("{}{}", (b'a' + position.column()) as char, position.row() + 1)
+}
+
+// The square a pawn moving `squares` on `board` skips, if it steps two ranks
+fn double_step(board: &Board, squares: (Position, Position)) -> Option<Position> {
+    let (src, dest) = squares;
+    let pawn = matches!(board.at_position(src), Slot::Occupied(Piece { kind: PieceKind::Pawn, .. }));
+    if !pawn || src.column() != dest.column() || src.row().abs_diff(dest.row()) != 2 {
+        return None;
+    }
+    Position::new(src.column(), (src.row() + dest.row()) / 2)
+}
+
+// Game for `board` with `turn` to move, for rebuilding one from a position
+// rather than from its moves. Debug builds check the position is one
+// rsoderh_chess can play; see `check_kings`.
+pub fn game_from(board: Board, turn: Color) -> Game {
+    debug_assert_eq!(check_kings(&board), Ok(()), "rebuilding a game with {turn:?} to move");
+    Game::new(board, turn)
+}
+
+// Whether each side has exactly one king, which finding checks relies on
+pub fn check_kings(board: &Board) -> Result<(), String> {
+    let mut kings = [0; 2];
+    for row in 0..8 {
+        for column in 0..8 {
+            let slot = board.at_position(Position::new(column, row).unwrap());
+            if let Slot::Occupied(Piece { kind: PieceKind::King, color }) = slot {
+                kings[usize::from(color == Color::Black)] += 1;
+            }
+        }
+    }
+    for (name, count) in [("White", kings[0]), ("Black", kings[1])] {
+        match count {
+            1 => {}
+            0 => return Err(format!("{name} has no king")),
+            n => return Err(format!("{name} has {n} kings")),
+        }
+    }
+    Ok(())
+}
+
+// Rank a pawn of `color` promotes on
+pub fn promotion_rank(color: Color) -> u8 {
+    if color == Color::White { 7 } else { 0 }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use puhl_gui::protocol::GameState;
+
+    fn pos(s: &str) -> Position {
+        Position::parse(s).unwrap()
+    }
+
+    // Play `moves`, given as (source, destination) squares, none of them promotions
+    pub(crate) fn play(state: &mut ChessState, moves: &[(&str, &str)]) {
+        for &(src, dest) in moves {
+            state.apply((pos(src), pos(dest)), None).expect("legal move");
+        }
+    }
+
+    pub(crate) fn state_with(pieces: &[(&str, Color, PieceKind)], turn: Color) -> ChessState {
+        let mut board = Board::new_empty();
+        for &(square, color, kind) in pieces {
+            *board.at_position_mut(pos(square)) = Slot::Occupied(Piece { color, kind });
+        }
+        ChessState::new(Game::new(board, turn))
+    }
+
+    #[test]
+    fn pawns_promote_on_the_far_rank() {
+        let state = state_with(
+            &[
+                ("e1", Color::White, PieceKind::King),
+                ("e8", Color::Black, PieceKind::King),
+                ("a7", Color::White, PieceKind::Pawn),
+                ("h2", Color::Black, PieceKind::Pawn),
+                ("c7", Color::White, PieceKind::Rook),
+            ],
+            Color::White,
+        );
+
+        assert_eq!(state.promotes(pos("a7"), pos("a8")), Some(Color::White));
+        assert_eq!(state.promotes(pos("a7"), pos("b8")), Some(Color::White));
+        assert_eq!(state.promotes(pos("h2"), pos("h1")), Some(Color::Black));
+        assert_eq!(state.promotes(pos("c7"), pos("c8")), None, "only pawns promote");
+        assert_eq!(state.promotes(pos("h2"), pos("h3")), None);
+        assert_eq!(state.promotes(pos("d4"), pos("d8")), None, "empty square");
+    }
+
+    #[test]
+    fn only_the_side_to_move_is_movable() {
+        let mut state = ChessState::default();
+        assert!(state.movable(pos("e2")));
+        assert!(!state.movable(pos("e7")), "Black's piece on White's turn");
+        assert!(!state.movable(pos("e4")), "empty square");
+
+        state
+            .apply((pos("e2"), pos("e4")), None)
+            .expect("legal move");
+        assert!(!state.movable(pos("e4")), "the piece that just moved");
+        assert!(state.movable(pos("e7")));
+    }
+
+    #[test]
+    fn square_names() {
+        assert_eq!(square_name(pos("a1")), "a1");
+        assert_eq!(square_name(pos("e4")), "e4");
+        assert_eq!(square_name(pos("h8")), "h8");
+    }
+
+    #[test]
+    fn promotion_rank_by_color() {
+        assert_eq!(promotion_rank(Color::White), 7);
+        assert_eq!(promotion_rank(Color::Black), 0);
+    }
+
+    #[test]
+    fn apply_reports_captures() {
+        let mut state = ChessState::default();
+        for (src, dest) in [("e2", "e4"), ("d7", "d5")] {
+            let applied = state
+                .apply((pos(src), pos(dest)), None)
+                .expect("legal move");
+            assert!(!applied.capture);
+        }
+
+        let applied = state
+            .apply((pos("e4"), pos("d5")), None)
+            .expect("legal capture");
+        assert!(applied.capture);
+        assert_eq!(state.turn(), Color::Black);
+        assert_eq!(state.outcome, None);
+    }
+
+    #[test]
+    fn undo_restores_the_previous_position() {
+        let mut state = ChessState::default();
+        assert!(!state.undo(), "nothing to take back");
+
+        let start = state.board().clone();
+        for (src, dest) in [("e2", "e4"), ("e7", "e5")] {
+            state
+                .apply((pos(src), pos(dest)), None)
+                .expect("legal move");
+        }
+        assert_eq!(state.halfmoves(), 2);
+
+        assert!(state.undo());
+        assert_eq!(state.halfmoves(), 1);
+        assert_eq!(state.turn(), Color::Black);
+        assert!(matches!(state.board().at_position(pos("e7")), Slot::Occupied(_)));
+
+        assert!(state.undo());
+        assert_eq!(state.board(), &start);
+        assert_eq!(state.turn(), Color::White);
+    }
+
+    #[test]
+    fn castling_is_detected_on_both_sides() {
+        let mut state = ChessState::default();
+        // Clears White's kingside and Black's queenside
+        let opening = [
+            ("e2", "e4"), ("e7", "e5"), ("g1", "f3"), ("b8", "c6"), ("f1", "c4"), ("g8", "f6"),
+            ("d2", "d4"), ("d7", "d5"), ("b1", "c3"), ("c8", "f5"), ("c1", "f4"), ("d8", "d7"),
+        ];
+        for (src, dest) in opening {
+            let applied = state
+                .apply((pos(src), pos(dest)), None)
+                .expect("legal move");
+            assert_eq!(applied.move_type, MoveType::Normal, "{src}{dest}");
+            assert_eq!(castling_rook(applied.move_type, pos(dest)), None);
+        }
+
+        let applied = state
+            .apply((pos("e1"), pos("g1")), None)
+            .expect("legal castle");
+        assert_eq!(applied.move_type, MoveType::CastleKingside);
+        assert_eq!(applied.notation, "O-O");
+        assert_eq!(castling_rook(applied.move_type, pos("g1")), Some((pos("h1"), pos("f1"))));
+
+        let applied = state
+            .apply((pos("e8"), pos("c8")), None)
+            .expect("legal castle");
+        assert_eq!(applied.move_type, MoveType::CastleQueenside);
+        assert_eq!(applied.notation, "O-O-O");
+        assert_eq!(castling_rook(applied.move_type, pos("c8")), Some((pos("a8"), pos("d8"))));
+    }
+
+    #[test]
+    fn fools_mate_ends_the_history() {
+        let mut state = ChessState::default();
+        for (src, dest) in [("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")] {
+            state
+                .apply((pos(src), pos(dest)), None)
+                .expect("legal move");
+        }
+
+        assert_eq!(state.outcome, Some(Outcome::Checkmate(Color::Black)));
+        assert_eq!(state.notations().last().map(|(_, _, notation)| notation), Some("Qd8-h4#"));
+        assert_eq!(state.history(), "1. f2-f3 e7-e5 2. g2-g4 Qd8-h4# 0-1");
+    }
+
+    #[test]
+    fn drawn_history_ends_with_half_points() {
+        let mut state = ChessState::default();
+        state
+            .apply((pos("g1"), pos("f3")), None)
+            .expect("legal move");
+        assert_eq!(state.history(), "1. Ng1-f3");
+
+        state.outcome = Some(Outcome::Draw(DrawReason::Repetition));
+        assert_eq!(state.history(), "1. Ng1-f3 ½-½");
+    }
+
+    #[test]
+    fn move_cache_is_cleared_by_moves() {
+        let mut state = ChessState::default();
+        assert_eq!(state.cached_moves(pos("g1")), None);
+        assert!(state.legal_moves(pos("g1")).contains(&pos("f3")));
+        assert!(state.cached_moves(pos("g1")).is_some_and(|moves| moves.contains(&pos("f3"))));
+        assert_eq!(state.cached_moves(pos("b1")), None);
+
+        state
+            .apply((pos("g1"), pos("f3")), None)
+            .expect("legal move");
+        assert_eq!(state.cached_moves(pos("g1")), None);
+        assert!(state.legal_moves(pos("g1")).is_empty());
+
+        assert!(state.undo());
+        assert_eq!(state.cached_moves(pos("g1")), None);
+        assert!(state.legal_moves(pos("g1")).contains(&pos("f3")));
+    }
+
+    #[test]
+    fn each_side_needs_exactly_one_king() {
+        let board = |pieces: &[(&str, Color)]| {
+            let mut board = Board::new_empty();
+            for &(square, color) in pieces {
+                *board.at_position_mut(pos(square)) = Slot::Occupied(Piece { color, kind: PieceKind::King });
+            }
+            board
+        };
+        assert_eq!(check_kings(Game::new_standard().board()), Ok(()));
+        assert_eq!(check_kings(&board(&[("e1", Color::White), ("e8", Color::Black)])), Ok(()));
+        assert_eq!(check_kings(&board(&[("e8", Color::Black)])), Err("White has no king".to_string()));
+        assert_eq!(check_kings(&board(&[("e1", Color::White)])), Err("Black has no king".to_string()));
+        assert_eq!(
+            check_kings(&board(&[("e1", Color::White), ("e8", Color::Black), ("a8", Color::Black)])),
+            Err("Black has 2 kings".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "rebuilding a game")]
+    fn game_from_catches_a_missing_king() {
+        game_from(Board::new_empty(), Color::White);
+    }
+
+    #[test]
+    fn double_step_sets_the_en_passant_target() {
+        let play = |state: &mut ChessState, src, dest| {
+            state.apply((pos(src), pos(dest)), None).expect("legal move")
+        };
+        let mut state = ChessState::default();
+        assert_eq!(state.en_passant(), None);
+        play(&mut state, "e2", "e4");
+        assert_eq!(state.en_passant(), Some(pos("e3")));
+        play(&mut state, "g8", "f6");
+        assert_eq!(state.en_passant(), None, "cleared by the next move");
+        play(&mut state, "d2", "d3");
+        assert_eq!(state.en_passant(), None, "a single step sets none");
+        play(&mut state, "c7", "c5");
+        assert_eq!(state.en_passant(), Some(pos("c6")));
+
+        assert!(state.undo());
+        assert_eq!(state.en_passant(), None);
+        assert!(state.undo());
+        assert!(state.undo());
+        assert_eq!(state.en_passant(), Some(pos("e3")), "undo brings it back");
+    }
+
+    #[test]
+    fn pin_cache_follows_the_position() {
+        let mut state = state_with(
+            &[
+                ("e1", Color::White, PieceKind::King),
+                ("e8", Color::Black, PieceKind::King),
+                ("e2", Color::White, PieceKind::Knight),
+                ("a5", Color::Black, PieceKind::Rook),
+            ],
+            Color::Black,
+        );
+        assert_eq!(state.cached_pins(), None);
+        assert!(state.pinned().is_empty());
+        assert_eq!(state.cached_pins(), Some(&[][..]));
+
+        state
+            .apply((pos("a5"), pos("e5")), None)
+            .expect("legal move");
+        assert_eq!(state.cached_pins(), None);
+        assert_eq!(state.pinned(), [pos("e2")]);
+
+        assert!(state.undo());
+        assert_eq!(state.cached_pins(), None);
+    }
+
+    #[test]
+    fn moving_declines_a_draw_offer() {
+        let mut state = ChessState::default();
+        let play = |state: &mut ChessState, src, dest| {
+            state.apply((pos(src), pos(dest)), None).expect("legal move")
+        };
+
+        // White offers along with its move, so its own move keeps the offer open
+        assert!(!state.offer_draw(Color::White));
+        assert!(!play(&mut state, "e2", "e4").declined_draw);
+        assert_eq!(state.draw_offer, Some(Color::White));
+
+        // Black moves instead of accepting
+        assert!(play(&mut state, "e7", "e5").declined_draw);
+        assert_eq!(state.draw_offer, None);
+        assert_eq!(state.outcome, None);
+    }
+
+    #[test]
+    fn accepting_a_draw_offer_ends_the_game() {
+        let mut state = ChessState::default();
+        assert!(!state.offer_draw(Color::White));
+        // Offering twice is still just one offer
+        assert!(!state.offer_draw(Color::White));
+        assert!(state.offer_draw(Color::Black));
+        assert_eq!(state.outcome, Some(Outcome::Draw(DrawReason::Agreement)));
+        assert_eq!(state.draw_offer, None);
+        assert!(!state.offer_draw(Color::White), "no offers after the game is over");
+    }
+
+    #[test]
+    fn board_at_earlier_plies() {
+        let mut state = ChessState::default();
+        let start = state.board().clone();
+        state
+            .apply((pos("e2"), pos("e4")), None)
+            .expect("legal move");
+
+        assert_eq!(state.board_at(0), Some(&start));
+        assert_eq!(state.board_at(1), Some(state.board()));
+        assert_eq!(state.board_at(2), None);
+    }
+
+    #[test]
+    fn fullmove_goes_up_after_black_moves() {
+        let mut state = ChessState::default();
+        assert_eq!(state.fullmove(), 1);
+        for (i, (src, dest)) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3")].into_iter().enumerate() {
+            state
+                .apply((pos(src), pos(dest)), None)
+                .expect("legal move");
+            assert_eq!(state.fullmove(), [1, 2, 2][i]);
+        }
+
+        assert!(state.undo());
+        assert!(state.undo());
+        assert_eq!(state.fullmove(), 1);
+
+        let state = state_with(&[("e1", Color::White, PieceKind::King)], Color::Black).with_fullmove(40);
+        assert_eq!(state.fullmove(), 40);
+    }
+
+    #[test]
+    fn debug_json_lists_the_side_to_move() {
+        let state = state_with(
+            &[("e1", Color::White, PieceKind::King), ("e8", Color::Black, PieceKind::King)],
+            Color::White,
+        );
+        let json = state.debug_json();
+        assert!(json.starts_with(&format!("{{\n  \"fen\": \"{}\",\n  \"turn\": \"white\"", state.fen())), "{json}");
+        assert!(json.contains("\"e1\": [") && json.contains("\"e2\""), "{json}");
+        assert!(!json.contains("\"e8\": ["), "{json}");
+        assert!(json.ends_with("]\n  }\n}"), "{json}");
+    }
+
+    #[test]
+    fn clicks_never_leave_an_empty_square_selected() {
+        let mut state = state_with(
+            &[("e1", Color::White, PieceKind::King), ("e8", Color::Black, PieceKind::King)],
+            Color::White,
+        );
+        assert_eq!(state.click(None, pos("d4")), Click::Deselect);
+        assert_eq!(state.click(None, pos("e8")), Click::Deselect);
+        assert_eq!(state.click(None, pos("e1")), Click::Select(pos("e1")));
+
+        // A stale selection of an empty or enemy square is dropped
+        assert_eq!(state.click(Some(pos("d4")), pos("d5")), Click::Deselect);
+        assert_eq!(state.click(Some(pos("e8")), pos("e7")), Click::Deselect);
+
+        assert_eq!(state.click(Some(pos("e1")), pos("e2")), Click::Move(pos("e1"), pos("e2")));
+        assert_eq!(state.click(Some(pos("e1")), pos("e1")), Click::Deselect);
+    }
+
+    #[test]
+    fn move_times_never_go_backwards() {
+        let mut state = ChessState::default();
+        for (src, dest) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3"), ("b8", "c6")] {
+            state
+                .apply((pos(src), pos(dest)), None)
+                .expect("legal move");
+        }
+
+        let times: Vec<_> = state.move_times().collect();
+        assert_eq!(times.len(), 4);
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]), "{times:?}");
+        let thinking = state.thinking_times();
+        assert_eq!(thinking.len(), 4);
+        assert!(thinking.iter().sum::<Duration>() <= state.started.elapsed());
+
+        state.undo();
+        assert_eq!(state.thinking_times().len(), 3);
+    }
+
+    #[test]
+    fn moves_out_of_turn_are_rejected() {
+        let mut state = ChessState::default();
+        assert_eq!(state.check_sender(Color::White, pos("e2")), Ok(()));
+        assert_eq!(state.check_sender(Color::Black, pos("e7")), Err("Black moved on White's turn".to_string()));
+
+        state
+            .apply((pos("e2"), pos("e4")), None)
+            .expect("legal move");
+        assert_eq!(state.check_sender(Color::Black, pos("e7")), Ok(()));
+        assert_eq!(state.check_sender(Color::White, pos("d2")), Err("White moved on Black's turn".to_string()));
+        assert_eq!(state.check_sender(Color::Black, pos("e4")), Err("Black moved White's piece on e4".to_string()));
+        assert_eq!(state.check_sender(Color::Black, pos("e5")), Err("Black moved from the empty square e5".to_string()));
+    }
+
+    #[test]
+    fn rejected_moves_change_nothing() {
+        let mut state = ChessState::default();
+        let fen = state.fen();
+        let err = state.apply((pos("e7"), pos("e5")), None);
+        assert!(err.is_err(), "Black can't move on White's turn");
+        assert_eq!(state.fen(), fen);
+        assert_eq!(state.halfmoves(), 0);
+
+        let applied = state.apply((pos("e2"), pos("e4")), None);
+        assert!(applied.is_ok());
+        assert_eq!(state.halfmoves(), 1);
+    }
+
+    fn remote_move(board: &Board, src: &str, dest: &str, prom_piece: Option<PieceKind>) -> MessageMove {
+        MessageMove {
+            board: board.clone(),
+            mv: (pos(src), pos(dest)),
+            prom_piece,
+            game_state: GameState::Ongoing,
+            move_type: None,
+        }
+    }
+
+    #[test]
+    fn illegal_remote_moves_change_nothing() {
+        let mut state = ChessState::default();
+        let fen = state.fen();
+        let board = state.board().clone();
+
+        // A promotion with no pawn on the seventh rank
+        let illegal = remote_move(&board, "e2", "e8", Some(PieceKind::Queen));
+        let err = state.apply_remote(Color::White, &illegal).unwrap_err();
+        assert!(matches!(err, RemoteMoveError::Desync(_)), "{err:?}");
+        assert_eq!(state.fen(), fen);
+        assert_eq!(state.halfmoves(), 0);
+
+        // A legal move whose board doesn't match ours is taken back
+        let wrong_board = remote_move(&board, "e2", "e4", None);
+        let err = state.apply_remote(Color::White, &wrong_board).unwrap_err();
+        assert!(matches!(err, RemoteMoveError::Desync(_)), "{err:?}");
+        assert_eq!(state.fen(), fen);
+        assert_eq!(state.halfmoves(), 0);
+
+        // Moving on the other side's turn
+        let out_of_turn = remote_move(&board, "e7", "e5", None);
+        let err = state.apply_remote(Color::Black, &out_of_turn).unwrap_err();
+        assert!(matches!(err, RemoteMoveError::ProtocolViolation(_)), "{err:?}");
+        assert_eq!(state.fen(), fen);
+
+        // The same move with the right board goes through
+        let mut after = ChessState::default();
+        after.apply((pos("e2"), pos("e4")), None).unwrap();
+        let good = remote_move(after.board(), "e2", "e4", None);
+        assert!(state.apply_remote(Color::White, &good).is_ok());
+        assert_eq!(state.fen(), after.fen());
+    }
+
+    #[test]
+    fn queen_promotion_is_recorded() {
+        let mut state = state_with(
+            &[
+                ("e1", Color::White, PieceKind::King),
+                ("e8", Color::Black, PieceKind::King),
+                ("a7", Color::White, PieceKind::Pawn),
+            ],
+            Color::White,
+        );
+        state.apply((pos("a7"), pos("a8")), Some(PieceKind::Queen)).expect("legal promotion");
+        assert!(matches!(
+            state.board().at_position(pos("a8")),
+            Slot::Occupied(Piece { color: Color::White, kind: PieceKind::Queen })
+        ));
+        assert!(matches!(state.board().at_position(pos("a7")), Slot::Empty));
+        assert_eq!(state.moves().collect::<Vec<_>>(), ["a7a8q"]);
+    }
+
+    #[test]
+    fn capture_promotion_keeps_its_source() {
+        let mut state = state_with(
+            &[
+                ("e1", Color::White, PieceKind::King),
+                ("e8", Color::Black, PieceKind::King),
+                ("a7", Color::White, PieceKind::Pawn),
+                ("b8", Color::Black, PieceKind::Knight),
+            ],
+            Color::White,
+        );
+        let applied = state.apply((pos("a7"), pos("b8")), Some(PieceKind::Queen)).expect("legal promotion");
+        assert_eq!(applied.notation, "a7xb8=Q");
+        assert!(applied.capture);
+        assert_eq!(state.moves().collect::<Vec<_>>(), ["a7b8q"]);
+        assert!(matches!(state.board().at_position(pos("a7")), Slot::Empty));
+    }
+}
@@ -0,0 +1,124 @@
+use ggez::graphics::Color;
+
+use crate::options::ThemeKind;
+
+// Colors of everything drawn around the board: the window background, the
+// status bar, banners, prompts and overlays. The squares and move highlights
+// have their own options.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    // Window background, seen in the borders around a letterboxed board
+    pub background: Color,
+    pub status_bar: Color,
+    pub status_text: Color,
+    // Status bar buttons, and the same greyed out while they don't apply
+    pub button: Color,
+    pub button_text: Color,
+    pub button_disabled: Color,
+    pub button_disabled_text: Color,
+    // Panels and buttons drawn over the board, e.g. Save PGN and Yes/No
+    pub panel: Color,
+    pub panel_text: Color,
+    // Promotion choices, tinted by the promoting side: darker and cool behind
+    // White's pieces, light and warm behind Black's
+    pub promotion_tile_white: Color,
+    pub promotion_tile_black: Color,
+    // Dimming behind prompts, and its text
+    pub dim: Color,
+    pub overlay_text: Color,
+    // Lighter dimming and fainter text, e.g. while the opponent is thinking
+    pub faint_dim: Color,
+    pub faint_text: Color,
+    pub banner_text: Color,
+    pub banner_outline: Color,
+    pub error_panel: Color,
+    // `--grid` lines between the squares and around the board
+    pub grid: Color,
+}
+
+impl Theme {
+    pub fn new(kind: ThemeKind) -> Self {
+        match kind {
+            ThemeKind::Light => Self::light(),
+            ThemeKind::Dark => Self::dark(),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            background: Color::WHITE,
+            status_bar: Color::from_rgb(0x30, 0x30, 0x30),
+            status_text: Color::WHITE,
+            button: Color::from_rgb(0xe0, 0xe0, 0xe0),
+            button_text: Color::BLACK,
+            button_disabled: Color::from_rgb(0x50, 0x50, 0x50),
+            button_disabled_text: Color::from_rgb(0x90, 0x90, 0x90),
+            panel: Color::from_rgba(240, 240, 240, 220),
+            panel_text: Color::BLACK,
+            promotion_tile_white: Color::from_rgba(0x5a, 0x6e, 0x8c, 230),
+            promotion_tile_black: Color::from_rgba(0xf0, 0xe4, 0xc8, 230),
+            dim: Color::from_rgba(0, 0, 0, 160),
+            overlay_text: Color::WHITE,
+            faint_dim: Color::from_rgba(0, 0, 0, 60),
+            faint_text: Color::from_rgba(255, 255, 255, 200),
+            banner_text: Color::WHITE,
+            banner_outline: Color::BLACK,
+            error_panel: Color::from_rgba(0x8B, 0x1A, 0x1A, 230),
+            grid: Color::from_rgba(0x30, 0x30, 0x30, 150),
+        }
+    }
+
+    // Dark background and panels with light text
+    pub fn dark() -> Self {
+        Self {
+            background: Color::from_rgb(0x1a, 0x1a, 0x1a),
+            status_bar: Color::from_rgb(0x10, 0x10, 0x10),
+            status_text: Color::from_rgb(0xe8, 0xe8, 0xe8),
+            button: Color::from_rgb(0x48, 0x48, 0x48),
+            button_text: Color::WHITE,
+            button_disabled: Color::from_rgb(0x26, 0x26, 0x26),
+            button_disabled_text: Color::from_rgb(0x6c, 0x6c, 0x6c),
+            panel: Color::from_rgba(0x38, 0x38, 0x38, 235),
+            panel_text: Color::WHITE,
+            promotion_tile_white: Color::from_rgba(0x46, 0x56, 0x70, 235),
+            promotion_tile_black: Color::from_rgba(0xd4, 0xc6, 0xa8, 235),
+            dim: Color::from_rgba(0, 0, 0, 190),
+            overlay_text: Color::from_rgb(0xf0, 0xf0, 0xf0),
+            faint_dim: Color::from_rgba(0, 0, 0, 90),
+            faint_text: Color::from_rgba(0xf0, 0xf0, 0xf0, 200),
+            banner_text: Color::WHITE,
+            banner_outline: Color::BLACK,
+            error_panel: Color::from_rgba(0x8B, 0x1A, 0x1A, 240),
+            grid: Color::from_rgba(0x10, 0x10, 0x10, 200),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // WCAG contrast ratio between two opaque colors
+    fn contrast(a: Color, b: Color) -> f32 {
+        let luminance = |c: Color| {
+            let channel = |v: f32| if v <= 0.03928 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) };
+            0.2126 * channel(c.r) + 0.7152 * channel(c.g) + 0.0722 * channel(c.b)
+        };
+        let (l1, l2) = (luminance(a), luminance(b));
+        (l1.max(l2) + 0.05) / (l1.min(l2) + 0.05)
+    }
+
+    #[test]
+    fn text_stands_out_in_both_themes() {
+        for theme in [Theme::light(), Theme::dark()] {
+            assert!(contrast(theme.status_text, theme.status_bar) >= 7.0);
+            assert!(contrast(theme.button_text, theme.button) >= 7.0);
+            assert!(contrast(theme.button_disabled_text, theme.button_disabled) >= 2.0);
+            assert!(contrast(theme.panel_text, theme.panel) >= 7.0);
+            assert!(contrast(theme.banner_text, theme.banner_outline) >= 7.0);
+            // The promoting side's pieces against their tiles
+            assert!(contrast(Color::WHITE, theme.promotion_tile_white) >= 4.5);
+            assert!(contrast(Color::BLACK, theme.promotion_tile_black) >= 4.5);
+        }
+    }
+}